@@ -1,4 +1,135 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// 通过 REST 接口以 JSON 收发的消息所在的 proto 文件
+const REST_PROTOS: &[&str] = &["proto/message.proto", "proto/user.proto"];
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/main.proto")?;
+    let messages = rest_messages()?;
+    let mut builder = tonic_build::configure();
+    for (name, _) in &messages {
+        builder = builder.type_attribute(
+            format!(".user_server.{}", name),
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        );
+    }
+    builder.compile(&["proto/main.proto"], &["proto"])?;
+    write_rpc_names("proto/main.proto")?;
+    write_openapi_schemas(&messages)?;
+    println!("cargo:rerun-if-changed=proto");
+    Ok(())
+}
+
+/// 生成 PbUser 服务全部 RPC 名称的列表, 启动时用于检查每个 RPC 都配置了访问策略
+fn write_rpc_names(proto: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let names: Vec<String> = fs::read_to_string(proto)?
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("rpc "))
+        .filter_map(|line| line.split(|c: char| c == '(' || c.is_whitespace()).next())
+        .map(|name| format!("{:?}", name))
+        .collect();
+    let out = Path::new(&env::var("OUT_DIR")?).join("rpc_names.rs");
+    fs::write(
+        out,
+        format!(
+            "pub const PB_USER_RPCS: &[&str] = &[{}];\n",
+            names.join(", ")
+        ),
+    )?;
+    Ok(())
+}
+
+/// proto 中的字段: 是否 repeated, 类型, 名称与行尾注释
+struct Field {
+    repeated: bool,
+    kind: String,
+    name: String,
+    comment: String,
+}
+
+/// 消息名称与字段
+type Message = (String, Vec<Field>);
+
+/// 读取 REST_PROTOS 中的全部消息, 不支持嵌套消息与 oneof
+fn rest_messages() -> Result<Vec<Message>, Box<dyn std::error::Error>> {
+    let mut messages = vec![];
+    for proto in REST_PROTOS {
+        let mut current: Option<Message> = None;
+        for line in fs::read_to_string(proto)?.lines() {
+            let (code, comment) = match line.split_once("//") {
+                Some((code, comment)) => (code.trim(), comment.trim()),
+                None => (line.trim(), ""),
+            };
+            if let Some(rest) = code.strip_prefix("message ") {
+                let name = rest.trim_end_matches('{').trim().to_string();
+                current = Some((name, vec![]));
+            } else if code == "}" {
+                messages.extend(current.take());
+            } else if let (Some((_, fields)), Some(declaration)) =
+                (current.as_mut(), code.strip_suffix(';'))
+            {
+                let mut words: Vec<&str> = declaration.split_whitespace().collect();
+                let repeated = words.first() == Some(&"repeated");
+                if repeated {
+                    words.remove(0);
+                }
+                if let [kind, name, "=", _] = words[..] {
+                    fields.push(Field {
+                        repeated,
+                        kind: kind.to_string(),
+                        name: name.to_string(),
+                        comment: comment.to_string(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(messages)
+}
+
+/// 由 proto 消息生成 OpenAPI 的 components.schemas (JSON)
+fn write_openapi_schemas(messages: &[Message]) -> Result<(), Box<dyn std::error::Error>> {
+    let schemas: Vec<String> = messages
+        .iter()
+        .map(|(name, fields)| {
+            let properties: Vec<String> = fields
+                .iter()
+                .map(|field| {
+                    let mut schema = match field.kind.as_str() {
+                        "string" => r#""type":"string""#.to_string(),
+                        "bool" => r#""type":"boolean""#.to_string(),
+                        "int64" | "uint64" | "sint64" | "fixed64" | "sfixed64" => {
+                            r#""type":"integer","format":"int64""#.to_string()
+                        }
+                        "int32" | "uint32" | "sint32" | "fixed32" | "sfixed32" => {
+                            r#""type":"integer","format":"int32""#.to_string()
+                        }
+                        "double" | "float" => r#""type":"number""#.to_string(),
+                        "bytes" => r#""type":"array","items":{"type":"integer"}"#.to_string(),
+                        kind => format!(r##""$ref":"#/components/schemas/{}""##, kind),
+                    };
+                    if field.repeated {
+                        schema = format!(r#""type":"array","items":{{{}}}"#, schema);
+                    }
+                    if !field.comment.is_empty() {
+                        schema = format!(
+                            r#"{},"description":"{}""#,
+                            schema,
+                            field.comment.replace('\\', "\\\\").replace('"', "\\\"")
+                        );
+                    }
+                    format!(r#""{}":{{{}}}"#, field.name, schema)
+                })
+                .collect();
+            format!(
+                r#""{}":{{"type":"object","properties":{{{}}}}}"#,
+                name,
+                properties.join(",")
+            )
+        })
+        .collect();
+    let out = Path::new(&env::var("OUT_DIR")?).join("openapi_schemas.json");
+    fs::write(out, format!("{{{}}}\n", schemas.join(",")))?;
     Ok(())
 }
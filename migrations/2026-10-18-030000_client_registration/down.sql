@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE `clients`
+ DROP COLUMN `registration_token_hash`,
+ DROP COLUMN `token_endpoint_auth_method`;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE `clients`
+ ADD COLUMN `token_endpoint_auth_method` varchar(32) CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT 'client_secret_basic' AFTER `refresh_token_ttl`,
+ ADD COLUMN `registration_token_hash` varchar(64) CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci DEFAULT NULL AFTER `token_endpoint_auth_method`;
+
+UPDATE `clients` SET `token_endpoint_auth_method` = 'none' WHERE `secret_hash` IS NULL;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE `federated_identities` (
+ `id` int unsigned NOT NULL AUTO_INCREMENT,
+ `provider` varchar(64) CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci NOT NULL,
+ `subject` varchar(255) CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci NOT NULL,
+ `user_id` int unsigned NOT NULL,
+ `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP,
+ PRIMARY KEY (`id`),
+ UNIQUE KEY `provider_subject` (`provider`,`subject`) USING BTREE,
+ KEY `user_id` (`user_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -3,25 +3,359 @@ use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::MysqlConnection;
 //use dotenv::dotenv;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::SocketAddr;
 //use tracing::info;
 
 pub type DbPool = Pool<ConnectionManager<MysqlConnection>>;
+pub type RedisPool = Pool<redis::Client>;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Config {
     pub listen_addr: SocketAddr,
+    #[serde(default = "default_http_listen_addr")]
+    pub http_listen_addr: SocketAddr,
+    /// JSON REST 接口的监听地址, 与 gRPC 共用 service 与访问策略
+    #[serde(default = "default_rest_listen_addr")]
+    pub rest_listen_addr: SocketAddr,
+    /// 允许通过 gRPC-Web 跨域调用的来源, 逗号分隔, * 为任意来源, 为空时只接受不带 Origin 的请求
+    #[serde(default)]
+    pub grpc_web_allowed_origins: String,
+    /// 浏览器跨域调用时允许携带的请求头
+    #[serde(default = "default_grpc_web_allowed_headers")]
+    pub grpc_web_allowed_headers: String,
+    /// 除 grpc-status 与 grpc-message 外允许浏览器读取的响应头
+    #[serde(default)]
+    pub grpc_web_exposed_headers: String,
+    /// HTTP 服务对外的地址, 用于 OpenID Connect discovery 中的各个端点
+    #[serde(default = "default_public_url")]
+    pub public_url: String,
     pub database_url: String,
+    #[serde(default = "default_redis_url")]
+    pub redis_url: String,
+    /// HS256/HS384/HS512 使用 jwt_secret_key, RS*/PS*/ES256/ES384/EdDSA 使用 PEM 密钥文件
+    #[serde(default = "default_jwt_algorithm")]
+    pub jwt_algorithm: String,
+    pub jwt_secret_key: Option<String>,
+    pub jwt_private_key_path: Option<String>,
+    pub jwt_public_key_path: Option<String>,
+    /// 单独配置的密钥在密钥环中的 kid, 也用于校验未携带 kid 的旧 token
+    #[serde(default = "default_jwt_key_id")]
+    pub jwt_key_id: String,
+    pub jwt_keys_dir: Option<String>,
+    pub jwt_active_kid: Option<String>,
+    /// 签发 token 的 iss, 校验时要求一致, 各环境应使用不同的值
+    #[serde(default = "default_jwt_issuer")]
+    pub jwt_issuer: String,
+    /// 逗号分隔的 aud, 签发时全部写入, 校验时要求至少包含其中之一
+    #[serde(default = "default_jwt_audience")]
+    pub jwt_audience: String,
+    /// 校验 exp 与 nbf 时允许的时钟偏差 (秒)
+    #[serde(default = "default_jwt_leeway")]
+    pub jwt_leeway: u64,
+    /// 逗号分隔的管理员用户 id, 收到 SIGHUP 时重新加载, 之后签发的 token 生效
+    #[serde(default)]
+    pub admin_user_ids: String,
+    /// token 有效期 (秒), 与 token_format 一样可在配置文件的 [clients.<client_id>] 中按客户端覆盖
+    #[serde(default = "default_access_token_ttl")]
+    pub access_token_ttl: u32,
+    #[serde(default = "default_refresh_token_ttl")]
+    pub refresh_token_ttl: u32,
+    /// 从登录开始计算的会话最长时间, 超过后 refresh token 无法再换取新的 token
+    #[serde(default = "default_max_session_lifetime")]
+    pub max_session_lifetime: u32,
+    #[serde(default)]
+    pub token_format: TokenFormat,
+    /// 空格分隔, 客户端可申请的 scope, 可按客户端覆盖
+    #[serde(default = "default_scope")]
+    pub scope: String,
+    /// id_token 有效期 (秒), 与 access token 的有效期无关
+    #[serde(default = "default_id_token_ttl")]
+    pub id_token_ttl: u32,
+    /// 授权码有效期 (秒), 授权码只能使用一次
+    #[serde(default = "default_authorization_code_ttl")]
+    pub authorization_code_ttl: u32,
+    /// 设备码有效期与最短轮询间隔 (秒)
+    #[serde(default = "default_device_code_ttl")]
+    pub device_code_ttl: u32,
+    #[serde(default = "default_device_poll_interval")]
+    pub device_poll_interval: u32,
+    /// 为 true 时动态注册客户端须携带管理员签发的 initial access token
+    #[serde(default = "default_true")]
+    pub registration_requires_initial_token: bool,
+    /// 不携带 initial access token 注册的客户端可申请的 scope, 不能使用 client_credentials 授权
+    #[serde(default = "default_anonymous_registration_scope")]
+    pub anonymous_registration_scope: String,
+    /// initial access token 默认有效期 (秒), 只能使用一次
+    #[serde(default = "default_initial_access_token_ttl")]
+    pub initial_access_token_ttl: u32,
+    #[serde(default)]
+    pub clients: HashMap<String, ClientSettings>,
+    /// 逗号分隔的密码认证后端, 按顺序尝试: database, ldap
+    #[serde(default = "default_authenticators")]
+    pub authenticators: String,
+    #[serde(default)]
+    pub ldap: Option<LdapSettings>,
+    /// 新密码哈希使用的 argon2 参数, 内存开销单位为 KiB. 登录时会升级用更弱参数生成的哈希,
+    /// 可用 server calibrate 在部署的机器上推荐参数
+    #[serde(default = "default_argon2_variant")]
+    pub argon2_variant: String,
+    #[serde(default = "default_argon2_memory_cost")]
+    pub argon2_memory_cost: u32,
+    #[serde(default = "default_argon2_iterations")]
+    pub argon2_iterations: u32,
+    #[serde(default = "default_argon2_parallelism")]
+    pub argon2_parallelism: u32,
+    /// 上游 OpenID Connect 身份提供方, 按名称配置
+    #[serde(default)]
+    pub providers: HashMap<String, ProviderSettings>,
+    /// 按 RPC 名称覆盖默认的访问策略: public, authenticated, admin 或 scope:<scope>
+    #[serde(default)]
+    pub rpc_policies: HashMap<String, String>,
+}
+
+/// jwt 为自包含的 JWT, reference 为随机句柄, 会话数据保存在 Redis 中
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenFormat {
+    #[default]
+    Jwt,
+    Reference,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TokenLifetimes {
+    pub access_token_ttl: u32,
+    pub refresh_token_ttl: u32,
+    pub max_session_lifetime: u32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ClientSettings {
+    pub access_token_ttl: Option<u32>,
+    pub refresh_token_ttl: Option<u32>,
+    pub max_session_lifetime: Option<u32>,
+    pub token_format: Option<TokenFormat>,
+    pub scope: Option<String>,
+}
+
+/// 上游身份提供方, 未配置的端点通过 issuer 的 discovery 文档获取.
+/// 回调地址为 {public_url}/federated/callback
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ProviderSettings {
+    pub issuer: String,
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+    /// 登录页面上显示的名称, 为空时使用配置中的名称
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default = "default_provider_scope")]
+    pub scope: String,
+    #[serde(default)]
+    pub authorization_endpoint: String,
+    #[serde(default)]
+    pub token_endpoint: String,
+    #[serde(default)]
+    pub jwks_uri: String,
+    /// 首次登录时按已验证的邮箱关联同邮箱的本地账号, 否则邮箱已被占用时拒绝登录
+    #[serde(default)]
+    pub link_existing_users: bool,
+}
+
+/// LDAP 认证: 按 search_filter 查找用户后以其 DN 与密码 bind.
+/// 首次登录时按属性创建或关联本地用户
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct LdapSettings {
+    pub url: String,
+    #[serde(default)]
+    pub starttls: bool,
+    /// 查找用户时使用的账号, 为空时匿名查找
+    #[serde(default)]
+    pub bind_dn: String,
+    #[serde(default)]
+    pub bind_password: String,
+    pub search_base: String,
+    /// {username} 替换为转义后的登录名
+    #[serde(default = "default_ldap_search_filter")]
+    pub search_filter: String,
+    #[serde(default = "default_ldap_email_attribute")]
+    pub email_attribute: String,
+    #[serde(default = "default_ldap_nickname_attribute")]
+    pub nickname_attribute: String,
+    /// 首次登录时关联同邮箱的本地账号, 否则邮箱已被占用时拒绝登录
+    #[serde(default)]
+    pub link_existing_users: bool,
+}
+
+fn default_authenticators() -> String {
+    "database".to_string()
+}
+
+fn default_argon2_variant() -> String {
+    "argon2id".to_string()
+}
+
+fn default_argon2_memory_cost() -> u32 {
+    4096
+}
+
+fn default_argon2_iterations() -> u32 {
+    3
+}
+
+fn default_argon2_parallelism() -> u32 {
+    4
+}
+
+fn default_ldap_search_filter() -> String {
+    "(mail={username})".to_string()
+}
+
+fn default_ldap_email_attribute() -> String {
+    "mail".to_string()
+}
+
+fn default_ldap_nickname_attribute() -> String {
+    "cn".to_string()
+}
+
+fn default_provider_scope() -> String {
+    "openid email profile".to_string()
+}
+
+fn default_http_listen_addr() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 8080))
+}
+
+fn default_rest_listen_addr() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 8081))
+}
+
+fn default_grpc_web_allowed_headers() -> String {
+    "authorization,refresh_token".to_string()
+}
+
+fn default_public_url() -> String {
+    "http://localhost:8080".to_string()
+}
+
+fn default_redis_url() -> String {
+    "redis://127.0.0.1:6379/".to_string()
+}
+
+fn default_jwt_algorithm() -> String {
+    "HS256".to_string()
+}
+
+fn default_jwt_key_id() -> String {
+    "default".to_string()
+}
+
+fn default_jwt_issuer() -> String {
+    "authorization-server".to_string()
+}
+
+/// 未配置时只有本服务自己是 token 的接收方
+fn default_jwt_audience() -> String {
+    "authorization-server".to_string()
+}
+
+fn default_jwt_leeway() -> u64 {
+    60
+}
+
+fn default_scope() -> String {
+    "openid profile email users:read profile:write password:write".to_string()
+}
+
+fn default_id_token_ttl() -> u32 {
+    300
+}
+
+fn default_authorization_code_ttl() -> u32 {
+    60
+}
+
+fn default_device_code_ttl() -> u32 {
+    600
+}
+
+fn default_device_poll_interval() -> u32 {
+    5
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_anonymous_registration_scope() -> String {
+    "openid profile email".to_string()
+}
+
+fn default_initial_access_token_ttl() -> u32 {
+    86400
+}
+
+fn default_access_token_ttl() -> u32 {
+    3600
+}
+
+fn default_refresh_token_ttl() -> u32 {
+    86400 * 7
+}
+
+fn default_max_session_lifetime() -> u32 {
+    86400 * 30
 }
 
 impl Config {
+    /// 读取当前目录下可选的 config.{toml,json,yaml} 配置文件, 环境变量优先.
+    /// 运行中收到 SIGHUP 时会重新读取, 以便在不重启的情况下轮换签名密钥
     pub fn try_from_env() -> Result<Self, ConfigError> {
         //dotenv().ok();
         let mut cfg = config::Config::new();
+        cfg.merge(config::File::with_name("config").required(false))?;
         cfg.merge(config::Environment::new())?;
         cfg.try_into()
     }
 
+    /// 客户端的 token 有效期, 未单独配置的项使用全局配置
+    pub fn token_lifetimes(&self, client_id: Option<&str>) -> TokenLifetimes {
+        let default = TokenLifetimes {
+            access_token_ttl: self.access_token_ttl,
+            refresh_token_ttl: self.refresh_token_ttl,
+            max_session_lifetime: self.max_session_lifetime,
+        };
+        match client_id.and_then(|id| self.clients.get(id)) {
+            Some(client) => TokenLifetimes {
+                access_token_ttl: client.access_token_ttl.unwrap_or(default.access_token_ttl),
+                refresh_token_ttl: client
+                    .refresh_token_ttl
+                    .unwrap_or(default.refresh_token_ttl),
+                max_session_lifetime: client
+                    .max_session_lifetime
+                    .unwrap_or(default.max_session_lifetime),
+            },
+            None => default,
+        }
+    }
+
+    pub fn token_format(&self, client_id: Option<&str>) -> TokenFormat {
+        client_id
+            .and_then(|id| self.clients.get(id))
+            .and_then(|client| client.token_format)
+            .unwrap_or(self.token_format)
+    }
+
+    /// 客户端可申请的 scope
+    pub fn allowed_scope(&self, client_id: Option<&str>) -> &str {
+        client_id
+            .and_then(|id| self.clients.get(id))
+            .and_then(|client| client.scope.as_deref())
+            .unwrap_or(&self.scope)
+    }
+
     pub async fn build_db_pool(&self) -> DbPool {
         let manager = ConnectionManager::<MysqlConnection>::new(&self.database_url);
         Pool::builder()
@@ -29,4 +363,28 @@ impl Config {
             .build(manager)
             .expect("failed to create db pool")
     }
+
+    pub async fn build_redis_pool(&self) -> RedisPool {
+        let client = redis::Client::open(self.redis_url.as_str()).expect("invalid redis url");
+        Pool::builder()
+            .max_size(10)
+            .build(client)
+            .expect("failed to create redis pool")
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// 测试使用的配置: 固定的 HS256 密钥, 数据库与 Redis 地址取自 DATABASE_URL 与 REDIS_URL.
+    /// jwt 的设置与密钥是全局的, 各测试须使用同一份配置
+    pub fn for_tests() -> Config {
+        serde_json::from_value(serde_json::json!({
+            "listen_addr": "127.0.0.1:0",
+            "database_url": std::env::var("DATABASE_URL").unwrap_or_default(),
+            "redis_url": std::env::var("REDIS_URL").unwrap_or_else(|_| default_redis_url()),
+            "jwt_secret_key": "test-secret-key",
+            "admin_user_ids": "1",
+        }))
+        .unwrap()
+    }
 }
@@ -1,4 +1,5 @@
 use chrono::ParseError as ChronoParseError;
+use config::ConfigError;
 use diesel::result::Error as DieselResultError;
 use jsonwebtoken::errors::Error as JWTError;
 use redis::RedisError;
@@ -7,7 +8,7 @@ use thiserror::Error;
 use tonic::Status;
 use tracing::error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum UserServerError {
     #[error("there are some problem while : {0}")]
     DatabaseError(String),
@@ -27,8 +28,36 @@ pub enum UserServerError {
     JWTGenerationError(String),
     #[error("jwt verify error : {0}")]
     JWTVerifyError(String),
+    #[error("jwt key error : {0}")]
+    KeyError(String),
     #[error("redis error : {0}")]
     RedisError(String),
+    #[error("configuration error : {0}")]
+    ConfigError(String),
+    #[error("permission denied : {0}")]
+    PermissionDenied(String),
+    #[error("client unauthorized : {0}")]
+    ClientUnauthorizedError(String),
+    #[error("invalid grant : {0}")]
+    InvalidGrant(String),
+    #[error("invalid scope : {0}")]
+    InvalidScope(String),
+    /// initial access token 或 registration access token 无效 (RFC 6750 3.1)
+    #[error("invalid token : {0}")]
+    InvalidToken(String),
+    #[error("invalid redirect uri : {0}")]
+    InvalidRedirectUri(String),
+    #[error("invalid client metadata : {0}")]
+    InvalidClientMetadata(String),
+    /// 用户拒绝了授权请求
+    #[error("access denied : {0}")]
+    AccessDenied(String),
+    /// 上游身份提供方不可用或返回了无效的响应
+    #[error("upstream provider error : {0}")]
+    UpstreamError(String),
+    /// 设备授权轮询的状态, 内容为 RFC 8628 3.5 的 error 参数
+    #[error("device authorization : {0}")]
+    DeviceAuthorization(&'static str),
 }
 
 impl From<SerdeError> for UserServerError {
@@ -61,6 +90,18 @@ impl From<RedisError> for UserServerError {
     }
 }
 
+impl From<ConfigError> for UserServerError {
+    fn from(err: ConfigError) -> Self {
+        UserServerError::ConfigError(err.to_string())
+    }
+}
+
+impl From<reqwest::Error> for UserServerError {
+    fn from(err: reqwest::Error) -> Self {
+        UserServerError::UpstreamError(err.to_string())
+    }
+}
+
 impl From<UserServerError> for Status {
     fn from(error: UserServerError) -> Self {
         match error {
@@ -71,6 +112,16 @@ impl From<UserServerError> for Status {
             UserServerError::JsonParseError(message) => Status::unavailable(message),
             UserServerError::JWTVerifyError(message) => Status::unauthenticated(message),
             UserServerError::PasswordUnauthorizedError(message) => Status::unauthenticated(message),
+            UserServerError::PermissionDenied(message) => Status::permission_denied(message),
+            UserServerError::ClientUnauthorizedError(message) => Status::unauthenticated(message),
+            UserServerError::InvalidGrant(message) => Status::unauthenticated(message),
+            UserServerError::InvalidScope(message) => Status::invalid_argument(message),
+            UserServerError::InvalidToken(message) => Status::unauthenticated(message),
+            UserServerError::InvalidRedirectUri(message) => Status::invalid_argument(message),
+            UserServerError::InvalidClientMetadata(message) => Status::invalid_argument(message),
+            UserServerError::AccessDenied(message) => Status::permission_denied(message),
+            UserServerError::UpstreamError(message) => Status::unavailable(message),
+            UserServerError::DeviceAuthorization(code) => Status::failed_precondition(code),
             _ => Status::internal("Internal Server Error".to_string()),
         }
     }
@@ -0,0 +1,183 @@
+use crate::model::response::{ClientToken, InitialAccessToken, Page};
+use crate::service::client::Client;
+use crate::user_server::{
+    ClientCredentialsResponse, ClientDestroyResponse, ClientIndexResponse, ClientRecord,
+    ClientStoreResponse, ClientUpdateResponse, InitialAccessTokenResponse, Message as PbMessage,
+    Response as PbResponse,
+};
+
+impl From<Client> for ClientRecord {
+    fn from(client: Client) -> ClientRecord {
+        ClientRecord {
+            client_id: client.client_id,
+            name: client.name,
+            grant_types: client.grant_types,
+            scope: client.scope,
+            redirect_uris: client.redirect_uris,
+            access_token_ttl: client.access_token_ttl.unwrap_or(0),
+            refresh_token_ttl: client.refresh_token_ttl.unwrap_or(0),
+            confidential: client.secret_hash.is_some(),
+        }
+    }
+}
+
+impl From<Page<Client>> for ClientIndexResponse {
+    fn from(page: Page<Client>) -> ClientIndexResponse {
+        ClientIndexResponse {
+            record: page.record.into_iter().map(ClientRecord::from).collect(),
+            meta: Some(page.meta.into()),
+        }
+    }
+}
+
+impl From<(Client, String)> for ClientStoreResponse {
+    fn from((client, client_secret): (Client, String)) -> ClientStoreResponse {
+        ClientStoreResponse {
+            client: Some(client.into()),
+            client_secret,
+        }
+    }
+}
+
+impl From<(Client, String)> for ClientUpdateResponse {
+    fn from((client, client_secret): (Client, String)) -> ClientUpdateResponse {
+        ClientUpdateResponse {
+            client: Some(client.into()),
+            client_secret,
+        }
+    }
+}
+
+impl From<bool> for ClientDestroyResponse {
+    fn from(result: bool) -> ClientDestroyResponse {
+        ClientDestroyResponse { result }
+    }
+}
+
+impl From<ClientToken> for ClientCredentialsResponse {
+    fn from(token: ClientToken) -> ClientCredentialsResponse {
+        ClientCredentialsResponse {
+            access_token: token.access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: token.expires_in,
+            scope: token.scope,
+        }
+    }
+}
+
+impl From<InitialAccessToken> for InitialAccessTokenResponse {
+    fn from(token: InitialAccessToken) -> InitialAccessTokenResponse {
+        InitialAccessTokenResponse {
+            initial_access_token: token.token,
+            expires_in: token.expires_in,
+        }
+    }
+}
+
+impl From<ClientIndexResponse> for PbMessage {
+    fn from(response: ClientIndexResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2012,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                client_index: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
+
+impl From<ClientStoreResponse> for PbMessage {
+    fn from(response: ClientStoreResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2013,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                client_store: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
+
+impl From<ClientUpdateResponse> for PbMessage {
+    fn from(response: ClientUpdateResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2014,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                client_update: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
+
+impl From<ClientDestroyResponse> for PbMessage {
+    fn from(response: ClientDestroyResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2015,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                client_destroy: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
+
+impl From<ClientCredentialsResponse> for PbMessage {
+    fn from(response: ClientCredentialsResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2016,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                client_credentials: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
+
+impl From<InitialAccessTokenResponse> for PbMessage {
+    fn from(response: InitialAccessTokenResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2017,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                initial_access_token: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::service::consent::Consent;
+use crate::user_server::{
+    ConsentRecord, ListConsentsResponse, Message as PbMessage, Response as PbResponse,
+    RevokeConsentResponse,
+};
+
+impl From<Consent> for ConsentRecord {
+    fn from(consent: Consent) -> ConsentRecord {
+        ConsentRecord {
+            client_id: consent.client_id,
+            client_name: consent.client_name,
+            scope: consent.scope,
+            created_at: consent.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            updated_at: consent.updated_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        }
+    }
+}
+
+impl From<Vec<Consent>> for ListConsentsResponse {
+    fn from(consents: Vec<Consent>) -> ListConsentsResponse {
+        ListConsentsResponse {
+            record: consents.into_iter().map(ConsentRecord::from).collect(),
+        }
+    }
+}
+
+impl From<u32> for RevokeConsentResponse {
+    fn from(revoked_sessions: u32) -> RevokeConsentResponse {
+        RevokeConsentResponse {
+            result: true,
+            revoked_sessions,
+        }
+    }
+}
+
+impl From<ListConsentsResponse> for PbMessage {
+    fn from(response: ListConsentsResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2018,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                list_consents: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
+
+impl From<RevokeConsentResponse> for PbMessage {
+    fn from(response: RevokeConsentResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2019,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                revoke_consent: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
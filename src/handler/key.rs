@@ -0,0 +1,29 @@
+use crate::model::response::SigningKeys;
+use crate::user_server::{Message as PbMessage, Response as PbResponse, RotateSigningKeyResponse};
+
+impl From<SigningKeys> for RotateSigningKeyResponse {
+    fn from(keys: SigningKeys) -> RotateSigningKeyResponse {
+        RotateSigningKeyResponse {
+            active_kid: keys.active_kid,
+            kids: keys.kids,
+        }
+    }
+}
+
+impl From<RotateSigningKeyResponse> for PbMessage {
+    fn from(response: RotateSigningKeyResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2008,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                rotate_signing_key: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
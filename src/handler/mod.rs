@@ -1,6 +1,11 @@
+use crate::error::UserServerError;
 use crate::user_server::{Message as PbMessage, Request as PbRequest};
 use tonic::Request;
 
+pub mod client;
+pub mod consent;
+pub mod key;
+pub mod token;
 pub mod user;
 
 impl From<Request<PbMessage>> for PbRequest {
@@ -8,3 +13,8 @@ impl From<Request<PbMessage>> for PbRequest {
         pb_message.get_ref().clone().request.unwrap()
     }
 }
+
+/// 取出请求中对应 RPC 的消息体, 缺失时返回 InvalidArgument 而不是 panic
+pub fn required<T>(body: Option<T>, name: &str) -> Result<T, UserServerError> {
+    body.ok_or_else(|| UserServerError::ArgumentError(format!("{} request required", name)))
+}
@@ -0,0 +1,87 @@
+use crate::model::response::Introspection;
+use crate::user_server::{
+    IntrospectTokenResponse, LogoutResponse, Message as PbMessage, Response as PbResponse,
+    RevokeTokenResponse,
+};
+
+impl From<bool> for RevokeTokenResponse {
+    fn from(result: bool) -> RevokeTokenResponse {
+        RevokeTokenResponse { result }
+    }
+}
+
+impl From<bool> for LogoutResponse {
+    fn from(result: bool) -> LogoutResponse {
+        LogoutResponse { result }
+    }
+}
+
+impl From<Introspection> for IntrospectTokenResponse {
+    fn from(introspection: Introspection) -> IntrospectTokenResponse {
+        IntrospectTokenResponse {
+            active: introspection.active,
+            sub: introspection.sub as i64,
+            email: introspection.email,
+            exp: introspection.exp as i64,
+            iat: introspection.iat as i64,
+            token_type: introspection.token_type,
+            scope: introspection.scope,
+            client_id: introspection.client_id,
+            jti: introspection.jti,
+        }
+    }
+}
+
+impl From<RevokeTokenResponse> for PbMessage {
+    fn from(response: RevokeTokenResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2009,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                revoke_token: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
+
+impl From<LogoutResponse> for PbMessage {
+    fn from(response: LogoutResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2010,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                logout: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
+
+impl From<IntrospectTokenResponse> for PbMessage {
+    fn from(response: IntrospectTokenResponse) -> PbMessage {
+        PbMessage {
+            msg_type: 2011,
+            sequence: 1,
+            response: Some(PbResponse {
+                result: true,
+                error_description: vec![0u8],
+                last_block: false,
+                block_index: 0,
+                introspect_token: Some(response),
+                ..Default::default()
+            }),
+            request: None,
+        }
+    }
+}
@@ -1,57 +1,85 @@
-use crate::config::DbPool;
+use crate::config::{Config, DbPool, RedisPool};
+use crate::error::UserServerError;
+use crate::handler::required;
+use crate::middleware::auth::Authenticated;
+use crate::middleware::policy::PolicyTable;
 use crate::model::response::{Meta, Page, Token};
+use crate::service::client as client_service;
+use crate::service::consent as consent_service;
+use crate::service::key as key_service;
+use crate::service::registration as registration_service;
+use crate::service::token as token_service;
 use crate::service::user as user_service;
 use crate::user_server::pb_user_server::PbUser;
+use crate::user_server::pb_user_server::PbUserServer as PbUserServerService;
 use crate::user_server::{
-    LoginResponse, Message as PbMessage, PaginationMeta, PasswordUpdateResponse,
-    RefreshTokenResponse, Request as PbRequest, Response as PbResponse, UserIndexResponse,
+    ClientCredentialsResponse, ClientDestroyResponse, ClientIndexResponse, ClientStoreResponse,
+    ClientUpdateResponse, InitialAccessTokenResponse, IntrospectTokenResponse,
+    ListConsentsResponse, LoginResponse, LogoutResponse, Message as PbMessage, PaginationMeta,
+    PasswordUpdateResponse, RefreshTokenResponse, Request as PbRequest, Response as PbResponse,
+    RevokeConsentResponse, RevokeTokenResponse, RotateSigningKeyResponse, UserIndexResponse,
     UserIndexResponseRecord, UserProfileUpdateResponse, UserShowResponse, UserStoreResponse,
+    PB_USER_RPCS,
 };
-use crate::util::jwt;
-use chrono::Local;
+use std::sync::Arc;
+use tonic::transport::NamedService;
 use tonic::{Request, Response, Status};
-use tracing::info;
 
-trait Middleware {
-    fn auth_check(&self) -> Result<bool, Status>;
+/// 各 RPC 默认的访问策略, 可在配置文件的 [rpc_policies] 中覆盖.
+/// 新增 RPC 时需在此声明, 否则服务无法启动. 管理类 RPC 在 handler 中另外校验管理员,
+/// 不受策略覆盖的影响
+const DEFAULT_POLICIES: &[(&str, &str)] = &[
+    ("UserIndex", "scope:users:read"),
+    ("UserShow", "scope:users:read"),
+    ("UserStore", "public"),
+    ("Login", "public"),
+    ("RefreshToken", "public"),
+    ("UserProfileUpdate", "scope:profile:write"),
+    ("PasswordUpdate", "scope:password:write"),
+    ("RotateSigningKey", "admin"),
+    ("RevokeToken", "public"),
+    ("Logout", "authenticated"),
+    ("IntrospectToken", "public"),
+    ("ClientIndex", "admin"),
+    ("ClientStore", "admin"),
+    ("ClientUpdate", "admin"),
+    ("ClientDestroy", "admin"),
+    ("ClientCredentials", "public"),
+    ("InitialAccessToken", "admin"),
+    ("ListConsents", "authenticated"),
+    ("RevokeConsent", "authenticated"),
+];
+
+pub fn policy_table(config: &Config) -> Result<PolicyTable, UserServerError> {
+    PolicyTable::new(
+        <PbUserServerService<PbUserServer>>::NAME,
+        PB_USER_RPCS,
+        DEFAULT_POLICIES,
+        &config.rpc_policies,
+    )
 }
 
-impl<T> Middleware for Request<T> {
-    fn auth_check(&self) -> Result<bool, Status> {
-        let token = self.metadata().get("authorization");
-        if let Some(t) = token {
-            let token_info = jwt::verify(t.to_str().unwrap_or(""))?;
-            if token_info.grant_type != "normal".to_string() {
-                info!("invalid auth token");
-                return Err(Status::unauthenticated("invalid auth token"));
-            }
-            let now: usize = Local::now().timestamp() as usize;
-            if token_info.exp > now {
-                let refresh_token = self.metadata().get("refresh_token");
-                if let Some(t) = refresh_token {
-                    let refresh_token_info = jwt::verify(t.to_str().unwrap_or(""))?;
-                    if refresh_token_info.grant_type != "refresh".to_string()
-                        || refresh_token_info.exp < now
-                    {
-                        info!("invalid auth token");
-                        return Err(Status::unauthenticated("invalid auth token"));
-                    }
-                }
-            }
-        } else {
-            info!("no valid auth token");
-            return Err(Status::unauthenticated("no valid auth token"));
-        }
-        Ok(true)
-    }
+/// RPC 的请求路径, REST 接口按此查找与 gRPC 相同的访问策略
+pub fn rpc_path(rpc: &str) -> String {
+    format!("/{}/{}", <PbUserServerService<PbUserServer>>::NAME, rpc)
 }
 
 pub struct PbUserServer {
     pub db_pool: DbPool,
+    pub redis_pool: RedisPool,
+    pub config: Arc<Config>,
 }
 
-pub async fn build_server(db_pool: DbPool) -> PbUserServer {
-    PbUserServer { db_pool }
+pub async fn build_server(
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: Arc<Config>,
+) -> PbUserServer {
+    PbUserServer {
+        db_pool,
+        redis_pool,
+        config,
+    }
 }
 
 impl From<Meta> for PaginationMeta {
@@ -71,7 +99,7 @@ impl From<Page<user_service::User>> for UserIndexResponse {
         for row in user.record {
             response_record.push(UserIndexResponseRecord {
                 id: row.id as i64,
-                email: row.email.unwrap(),
+                email: row.email.unwrap_or_default(),
                 nickname: row.nickname.unwrap_or("".to_string()),
             });
         }
@@ -86,7 +114,7 @@ impl From<user_service::User> for UserIndexResponseRecord {
     fn from(user: user_service::User) -> UserIndexResponseRecord {
         UserIndexResponseRecord {
             id: user.id as i64,
-            email: user.email.unwrap_or("asd".to_string()),
+            email: user.email.unwrap_or_default(),
             nickname: user.nickname.unwrap_or("".to_string()),
         }
     }
@@ -96,7 +124,7 @@ impl From<user_service::User> for UserShowResponse {
     fn from(user: user_service::User) -> UserShowResponse {
         UserShowResponse {
             id: user.id as i64,
-            email: user.email.unwrap_or("adsf".to_string()),
+            email: user.email.unwrap_or_default(),
             nickname: user.nickname.unwrap_or("".to_string()),
         }
     }
@@ -160,12 +188,7 @@ impl From<UserIndexResponse> for PbMessage {
                 last_block: false,
                 block_index: 0,
                 user_index: Some(response),
-                user_show: None,
-                user_store: None,
-                login: None,
-                refresh_token: None,
-                user_profile_update: None,
-                password_update: None,
+                ..Default::default()
             }),
             request: None,
         }
@@ -182,13 +205,8 @@ impl From<UserShowResponse> for PbMessage {
                 error_description: vec![0u8],
                 last_block: false,
                 block_index: 0,
-                user_index: None,
                 user_show: Some(response),
-                user_store: None,
-                login: None,
-                refresh_token: None,
-                user_profile_update: None,
-                password_update: None,
+                ..Default::default()
             }),
             request: None,
         }
@@ -205,13 +223,8 @@ impl From<UserStoreResponse> for PbMessage {
                 error_description: vec![0u8],
                 last_block: false,
                 block_index: 0,
-                user_index: None,
-                user_show: None,
                 user_store: Some(response),
-                login: None,
-                refresh_token: None,
-                user_profile_update: None,
-                password_update: None,
+                ..Default::default()
             }),
             request: None,
         }
@@ -228,13 +241,8 @@ impl From<LoginResponse> for PbMessage {
                 error_description: vec![0u8],
                 last_block: false,
                 block_index: 0,
-                user_index: None,
-                user_show: None,
-                user_store: None,
                 login: Some(response),
-                refresh_token: None,
-                user_profile_update: None,
-                password_update: None,
+                ..Default::default()
             }),
             request: None,
         }
@@ -251,13 +259,8 @@ impl From<RefreshTokenResponse> for PbMessage {
                 error_description: vec![0u8],
                 last_block: false,
                 block_index: 0,
-                user_index: None,
-                user_show: None,
-                user_store: None,
-                login: None,
                 refresh_token: Some(response),
-                user_profile_update: None,
-                password_update: None,
+                ..Default::default()
             }),
             request: None,
         }
@@ -274,13 +277,8 @@ impl From<UserProfileUpdateResponse> for PbMessage {
                 error_description: vec![0u8],
                 last_block: false,
                 block_index: 0,
-                user_index: None,
-                user_show: None,
-                user_store: None,
-                login: None,
-                refresh_token: None,
                 user_profile_update: Some(response),
-                password_update: None,
+                ..Default::default()
             }),
             request: None,
         }
@@ -297,13 +295,8 @@ impl From<PasswordUpdateResponse> for PbMessage {
                 error_description: vec![0u8],
                 last_block: false,
                 block_index: 0,
-                user_index: None,
-                user_show: None,
-                user_store: None,
-                login: None,
-                refresh_token: None,
-                user_profile_update: None,
                 password_update: Some(response),
+                ..Default::default()
             }),
             request: None,
         }
@@ -313,9 +306,14 @@ impl From<PasswordUpdateResponse> for PbMessage {
 #[tonic::async_trait]
 impl PbUser for PbUserServer {
     async fn user_index(&self, request: Request<PbMessage>) -> Result<Response<PbMessage>, Status> {
+        let claims = request.claims()?;
         let pb_request = PbRequest::from(request);
-        let db_result =
-            user_service::user_index(pb_request.user_index.unwrap(), self.db_pool.clone()).await?;
+        let db_result = user_service::user_index(
+            required(pb_request.user_index, "user_index")?,
+            claims,
+            self.db_pool.clone(),
+        )
+        .await?;
 
         Ok(Response::new(PbMessage::from(UserIndexResponse::from(
             db_result,
@@ -323,24 +321,38 @@ impl PbUser for PbUserServer {
     }
 
     async fn user_show(&self, request: Request<PbMessage>) -> Result<Response<PbMessage>, Status> {
+        let claims = request.claims()?;
         let pb_request = PbRequest::from(request);
-        let db_result =
-            user_service::user_show(pb_request.user_show.unwrap(), self.db_pool.clone()).await?;
+        let db_result = user_service::user_show(
+            required(pb_request.user_show, "user_show")?,
+            claims,
+            self.db_pool.clone(),
+        )
+        .await?;
         let pb_response = UserShowResponse::from(db_result);
         Ok(Response::new(PbMessage::from(pb_response)))
     }
 
     async fn user_store(&self, request: Request<PbMessage>) -> Result<Response<PbMessage>, Status> {
         let pb_request = PbRequest::from(request);
-        let db_result =
-            user_service::user_store(pb_request.user_store.unwrap(), self.db_pool.clone()).await?;
+        let db_result = user_service::user_store(
+            required(pb_request.user_store, "user_store")?,
+            self.db_pool.clone(),
+        )
+        .await?;
         let pb_response = UserStoreResponse::from(db_result);
         Ok(Response::new(PbMessage::from(pb_response)))
     }
 
     async fn login(&self, request: Request<PbMessage>) -> Result<Response<PbMessage>, Status> {
         let pb_request = PbRequest::from(request);
-        let token = user_service::login(pb_request.login.unwrap(), self.db_pool.clone()).await?;
+        let token = user_service::login(
+            required(pb_request.login, "login")?,
+            self.db_pool.clone(),
+            self.redis_pool.clone(),
+            self.config.clone(),
+        )
+        .await?;
         let pb_response = LoginResponse::from(token);
         Ok(Response::new(PbMessage::from(pb_response)))
     }
@@ -350,7 +362,13 @@ impl PbUser for PbUserServer {
         request: Request<PbMessage>,
     ) -> Result<Response<PbMessage>, Status> {
         let pb_request = PbRequest::from(request);
-        let token = user_service::refresh_token(pb_request.refresh_token.unwrap()).await?;
+        let token = user_service::refresh_token(
+            required(pb_request.refresh_token, "refresh_token")?,
+            self.db_pool.clone(),
+            self.redis_pool.clone(),
+            self.config.clone(),
+        )
+        .await?;
         let pb_response = RefreshTokenResponse::from(token);
         Ok(Response::new(PbMessage::from(pb_response)))
     }
@@ -359,9 +377,11 @@ impl PbUser for PbUserServer {
         &self,
         request: Request<PbMessage>,
     ) -> Result<Response<PbMessage>, Status> {
+        let claims = request.claims()?;
         let pb_request = PbRequest::from(request);
         let db_result = user_service::user_profile_update(
-            pb_request.user_profile_update.unwrap(),
+            required(pb_request.user_profile_update, "user_profile_update")?,
+            claims,
             self.db_pool.clone(),
         )
         .await?;
@@ -373,14 +393,233 @@ impl PbUser for PbUserServer {
         &self,
         request: Request<PbMessage>,
     ) -> Result<Response<PbMessage>, Status> {
-        request.auth_check()?;
+        let claims = request.claims()?;
         let pb_request = PbRequest::from(request);
         let db_result = user_service::password_update(
-            pb_request.password_update.unwrap(),
+            required(pb_request.password_update, "password_update")?,
+            claims,
             self.db_pool.clone(),
         )
         .await?;
         let pb_response = PasswordUpdateResponse::from(db_result);
         Ok(Response::new(PbMessage::from(pb_response)))
     }
+
+    async fn rotate_signing_key(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        // 策略表可被配置覆盖, 此处仍要求管理员
+        user_service::check_admin(&request.claims()?)?;
+        let pb_request = PbRequest::from(request);
+        let signing_keys = key_service::rotate_signing_key(required(
+            pb_request.rotate_signing_key,
+            "rotate_signing_key",
+        )?)
+        .await?;
+        let pb_response = RotateSigningKeyResponse::from(signing_keys);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn revoke_token(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        let pb_request = PbRequest::from(request);
+        let result = token_service::revoke_token(
+            required(pb_request.revoke_token, "revoke_token")?,
+            self.db_pool.clone(),
+            self.redis_pool.clone(),
+        )
+        .await?;
+        let pb_response = RevokeTokenResponse::from(result);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn logout(&self, request: Request<PbMessage>) -> Result<Response<PbMessage>, Status> {
+        let claims = request.claims()?;
+        let pb_request = PbRequest::from(request);
+        let result = token_service::logout(
+            required(pb_request.logout, "logout")?,
+            claims,
+            self.redis_pool.clone(),
+        )
+        .await?;
+        let pb_response = LogoutResponse::from(result);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn introspect_token(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        let pb_request = PbRequest::from(request);
+        let introspection = token_service::introspect_token(
+            required(pb_request.introspect_token, "introspect_token")?,
+            self.db_pool.clone(),
+            self.redis_pool.clone(),
+        )
+        .await?;
+        let pb_response = IntrospectTokenResponse::from(introspection);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn client_index(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        user_service::check_admin(&request.claims()?)?;
+        let pb_request = PbRequest::from(request);
+        let db_result = client_service::client_index(
+            required(pb_request.client_index, "client_index")?,
+            self.db_pool.clone(),
+        )
+        .await?;
+        let pb_response = ClientIndexResponse::from(db_result);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn client_store(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        user_service::check_admin(&request.claims()?)?;
+        let pb_request = PbRequest::from(request);
+        let db_result = client_service::client_store(
+            required(pb_request.client_store, "client_store")?,
+            self.db_pool.clone(),
+        )
+        .await?;
+        let pb_response = ClientStoreResponse::from(db_result);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn client_update(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        user_service::check_admin(&request.claims()?)?;
+        let pb_request = PbRequest::from(request);
+        let db_result = client_service::client_update(
+            required(pb_request.client_update, "client_update")?,
+            self.db_pool.clone(),
+        )
+        .await?;
+        let pb_response = ClientUpdateResponse::from(db_result);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn client_destroy(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        user_service::check_admin(&request.claims()?)?;
+        let pb_request = PbRequest::from(request);
+        let result = client_service::client_destroy(
+            required(pb_request.client_destroy, "client_destroy")?,
+            self.db_pool.clone(),
+        )
+        .await?;
+        let pb_response = ClientDestroyResponse::from(result);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn client_credentials(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        let pb_request = PbRequest::from(request);
+        let token = client_service::client_credentials(
+            required(pb_request.client_credentials, "client_credentials")?,
+            self.db_pool.clone(),
+            self.redis_pool.clone(),
+            &self.config,
+        )
+        .await?;
+        let pb_response = ClientCredentialsResponse::from(token);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn initial_access_token(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        user_service::check_admin(&request.claims()?)?;
+        let pb_request = PbRequest::from(request);
+        let token = registration_service::initial_access_token(
+            required(pb_request.initial_access_token, "initial_access_token")?,
+            self.redis_pool.clone(),
+            &self.config,
+        )
+        .await?;
+        let pb_response = InitialAccessTokenResponse::from(token);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn list_consents(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        let claims = request.claims()?;
+        let pb_request = PbRequest::from(request);
+        let consents = consent_service::list_consents(
+            required(pb_request.list_consents, "list_consents")?,
+            claims,
+            self.db_pool.clone(),
+        )
+        .await?;
+        let pb_response = ListConsentsResponse::from(consents);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+
+    async fn revoke_consent(
+        &self,
+        request: Request<PbMessage>,
+    ) -> Result<Response<PbMessage>, Status> {
+        let claims = request.claims()?;
+        let pb_request = PbRequest::from(request);
+        let revoked = consent_service::revoke_consent(
+            required(pb_request.revoke_consent, "revoke_consent")?,
+            claims,
+            self.db_pool.clone(),
+            self.redis_pool.clone(),
+        )
+        .await?;
+        let pb_response = RevokeConsentResponse::from(revoked);
+        Ok(Response::new(PbMessage::from(pb_response)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 外部账号未提供已验证的邮箱时创建的用户没有邮箱
+    #[test]
+    fn user_index_without_email() {
+        let page = Page::build(
+            vec![user_service::User {
+                id: 7,
+                email: None,
+                nickname: Some("新用户".to_string()),
+                gender: Some(0),
+                birthday: None,
+            }],
+            10,
+            0,
+            1,
+        );
+        let response = UserIndexResponse::from(page);
+        assert_eq!(response.record.len(), 1);
+        assert_eq!(response.record[0].id, 7);
+        assert_eq!(response.record[0].email, "");
+    }
+
+    #[test]
+    fn missing_request_body_is_invalid_argument() {
+        let pb_request = PbRequest::default();
+        let status = Status::from(required(pb_request.login, "login").unwrap_err());
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(status.message(), "login request required");
+    }
 }
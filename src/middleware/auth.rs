@@ -0,0 +1,133 @@
+use crate::config::RedisPool;
+use crate::error::UserServerError;
+use crate::middleware::policy::PolicyTable;
+use crate::service::token as token_service;
+use crate::util::jwt::Claims;
+use http::HeaderMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::{Request, Status};
+use tower::{Layer, Service};
+use tracing::info;
+
+/// 在请求到达 handler 之前校验 access token, 并将解析出的 `Claims` 放入请求的 extensions.
+/// 是否允许调用由策略表决定
+#[derive(Clone)]
+pub struct AuthLayer {
+    redis_pool: RedisPool,
+    policies: Arc<PolicyTable>,
+}
+
+impl AuthLayer {
+    pub fn new(redis_pool: RedisPool, policies: PolicyTable) -> AuthLayer {
+        AuthLayer {
+            redis_pool,
+            policies: Arc::new(policies),
+        }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            redis_pool: self.redis_pool.clone(),
+            policies: self.policies.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    redis_pool: RedisPool,
+    policies: Arc<PolicyTable>,
+}
+
+impl<S, B> Service<http::Request<B>> for AuthService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let redis_pool = self.redis_pool.clone();
+        let policies = self.policies.clone();
+
+        Box::pin(async move {
+            let claims = authenticate(&redis_pool, request.headers());
+            let policy = policies.get(request.uri().path());
+            if let Err(err) = policy.check(claims.as_ref()) {
+                info!("{} rejected: {}", request.uri().path(), err);
+                return Ok(Status::from(err).to_http());
+            }
+            if let Ok(claims) = claims {
+                request.extensions_mut().insert(claims);
+            }
+            inner.call(request).await
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for AuthService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+/// 校验 authorization 中的 access token, 允许带有 Bearer 前缀.
+/// 已吊销或所属家族已被删除的 token 均被拒绝
+pub fn authenticate(
+    redis_pool: &RedisPool,
+    headers: &HeaderMap,
+) -> Result<Claims, UserServerError> {
+    let token = match headers.get("authorization") {
+        Some(token) => token.to_str().unwrap_or(""),
+        None => {
+            return Err(UserServerError::JWTVerifyError(
+                "no valid auth token".to_string(),
+            ))
+        }
+    };
+    let token = token.strip_prefix("Bearer ").unwrap_or(token);
+    let token_info = token_service::resolve(redis_pool, token)?;
+    if token_info.grant_type != "normal" {
+        return Err(UserServerError::JWTVerifyError(
+            "invalid auth token".to_string(),
+        ));
+    }
+    if !token_service::is_active_access_token(redis_pool, &token_info)? {
+        return Err(UserServerError::JWTVerifyError(
+            "revoked auth token".to_string(),
+        ));
+    }
+    Ok(token_info)
+}
+
+/// 取出认证层放入的 `Claims`
+pub trait Authenticated {
+    fn claims(&self) -> Result<Claims, UserServerError>;
+}
+
+impl<T> Authenticated for Request<T> {
+    fn claims(&self) -> Result<Claims, UserServerError> {
+        self.extensions()
+            .get::<Claims>()
+            .cloned()
+            .ok_or_else(|| UserServerError::JWTVerifyError("no valid auth token".to_string()))
+    }
+}
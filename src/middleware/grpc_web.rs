@@ -0,0 +1,151 @@
+use crate::config::Config;
+use crate::error::UserServerError;
+use http::header::{HeaderName, HeaderValue, ACCESS_CONTROL_REQUEST_HEADERS};
+use http::{Method, StatusCode};
+use hyper::Body;
+use std::convert::TryFrom;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::body::{empty_body, BoxBody};
+use tonic::transport::NamedService;
+use tower::Service;
+use tracing::info;
+
+/// gRPC-Web 客户端自身会发送的请求头, 总是允许
+const GRPC_WEB_HEADERS: &[&str] = &["content-type", "x-grpc-web", "x-user-agent", "grpc-timeout"];
+
+/// 在同一端口上接受 gRPC-Web (binary 与 text) 请求, 并按配置处理 CORS.
+/// tonic-web 会原样允许预检请求中的全部请求头, 这里先拒绝未配置的请求头
+#[derive(Clone)]
+pub struct GrpcWebService<S> {
+    inner: S,
+    allowed_headers: Arc<Vec<HeaderName>>,
+}
+
+/// 用 tonic-web 包装 service, tonic-web 未导出包装后的类型, 只能以 impl Trait 返回
+#[allow(clippy::type_complexity)]
+pub fn enable<S>(
+    config: &Config,
+    service: S,
+) -> Result<
+    GrpcWebService<
+        impl Service<
+                http::Request<Body>,
+                Response = http::Response<BoxBody>,
+                Error = S::Error,
+                Future = impl Send + 'static,
+            > + NamedService
+            + Clone
+            + Send
+            + 'static,
+    >,
+    UserServerError,
+>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>>,
+    S: NamedService + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>> + Send,
+{
+    let mut cors = tonic_web::config().allow_credentials(false);
+    let origins = split(&config.grpc_web_allowed_origins);
+    if origins.contains(&"*") {
+        cors = cors.allow_all_origins();
+    } else {
+        let origins = origins
+            .into_iter()
+            .map(|origin| match HeaderValue::from_str(origin) {
+                Ok(value) if origin.contains("://") => Ok(value),
+                _ => Err(UserServerError::ConfigError(format!(
+                    "invalid grpc-web origin {}",
+                    origin
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        cors = cors.allow_origins(origins);
+    }
+    cors = cors.expose_headers(header_names(&config.grpc_web_exposed_headers)?);
+
+    let mut allowed_headers = header_names(&config.grpc_web_allowed_headers)?;
+    allowed_headers.extend(
+        GRPC_WEB_HEADERS
+            .iter()
+            .map(|name| HeaderName::from_static(name)),
+    );
+    Ok(GrpcWebService {
+        inner: cors.enable(service),
+        allowed_headers: Arc::new(allowed_headers),
+    })
+}
+
+impl<S> GrpcWebService<S> {
+    /// 预检请求中第一个未允许的请求头
+    fn disallowed_header(&self, request: &http::Request<Body>) -> Option<String> {
+        let requested = request.headers().get(ACCESS_CONTROL_REQUEST_HEADERS)?;
+        let requested = match requested.to_str() {
+            Ok(requested) => requested,
+            Err(_) => return Some(format!("{:?}", requested)),
+        };
+        split(requested)
+            .into_iter()
+            .map(str::to_ascii_lowercase)
+            .find(|name| {
+                !self
+                    .allowed_headers
+                    .iter()
+                    .any(|allowed| allowed.as_str() == name)
+            })
+    }
+}
+
+fn split(list: &str) -> Vec<&str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn header_names(list: &str) -> Result<Vec<HeaderName>, UserServerError> {
+    split(list)
+        .into_iter()
+        .map(|name| {
+            HeaderName::try_from(name).map_err(|_| {
+                UserServerError::ConfigError(format!("invalid grpc-web header {}", name))
+            })
+        })
+        .collect()
+}
+
+impl<S> Service<http::Request<Body>> for GrpcWebService<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        if request.method() == Method::OPTIONS {
+            if let Some(header) = self.disallowed_header(&request) {
+                info!("grpc-web preflight rejected: header {} not allowed", header);
+                let response = http::Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(empty_body())
+                    .unwrap();
+                return Box::pin(async move { Ok(response) });
+            }
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+impl<S: NamedService> NamedService for GrpcWebService<S> {
+    const NAME: &'static str = S::NAME;
+}
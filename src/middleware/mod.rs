@@ -0,0 +1,3 @@
+pub mod auth;
+pub mod grpc_web;
+pub mod policy;
@@ -0,0 +1,115 @@
+use crate::error::UserServerError;
+use crate::util::jwt::Claims;
+use std::collections::HashMap;
+use std::fmt;
+
+/// RPC 的访问策略, 配置中写作 public, authenticated, admin 或 scope:<scope>
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccessPolicy {
+    Public,
+    Authenticated,
+    Admin,
+    Scope(String),
+}
+
+impl AccessPolicy {
+    pub fn parse(policy: &str) -> Result<AccessPolicy, UserServerError> {
+        match policy.trim() {
+            "public" => Ok(AccessPolicy::Public),
+            "authenticated" => Ok(AccessPolicy::Authenticated),
+            "admin" => Ok(AccessPolicy::Admin),
+            other if other.starts_with("scope:") && other.len() > "scope:".len() => {
+                Ok(AccessPolicy::Scope(other["scope:".len()..].to_string()))
+            }
+            other => Err(UserServerError::ConfigError(format!(
+                "unknown access policy {}",
+                other
+            ))),
+        }
+    }
+
+    /// 根据认证结果判断是否允许调用, public 的 RPC 即使 token 无效也放行
+    pub fn check(&self, claims: Result<&Claims, &UserServerError>) -> Result<(), UserServerError> {
+        match (self, claims) {
+            (AccessPolicy::Public, _) => Ok(()),
+            (_, Err(err)) => Err(err.clone()),
+            (AccessPolicy::Authenticated, Ok(_)) => Ok(()),
+            (AccessPolicy::Admin, Ok(claims)) if claims.admin => Ok(()),
+            (AccessPolicy::Admin, Ok(_)) => Err(UserServerError::PermissionDenied(
+                "admin privilege required".to_string(),
+            )),
+            (AccessPolicy::Scope(scope), Ok(claims)) if claims.has_scope(scope) => Ok(()),
+            (AccessPolicy::Scope(scope), Ok(_)) => Err(UserServerError::PermissionDenied(format!(
+                "insufficient scope, {} required",
+                scope
+            ))),
+        }
+    }
+}
+
+/// 与配置中的写法一致
+impl fmt::Display for AccessPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessPolicy::Public => write!(f, "public"),
+            AccessPolicy::Authenticated => write!(f, "authenticated"),
+            AccessPolicy::Admin => write!(f, "admin"),
+            AccessPolicy::Scope(scope) => write!(f, "scope:{}", scope),
+        }
+    }
+}
+
+/// 以请求路径 (/<package>.<service>/<rpc>) 为键的访问策略表
+#[derive(Clone)]
+pub struct PolicyTable {
+    policies: HashMap<String, AccessPolicy>,
+}
+
+impl PolicyTable {
+    /// 以 defaults 为基础, 按配置覆盖各 RPC 的策略.
+    /// 任一 RPC 缺少策略或配置了不存在的 RPC 时返回错误, 以免新增的 RPC 被遗漏
+    pub fn new(
+        service: &str,
+        rpcs: &[&str],
+        defaults: &[(&str, &str)],
+        overrides: &HashMap<String, String>,
+    ) -> Result<PolicyTable, UserServerError> {
+        let mut policies = HashMap::new();
+        for (rpc, policy) in defaults {
+            policies.insert(rpc.to_string(), AccessPolicy::parse(policy)?);
+        }
+        for (rpc, policy) in overrides {
+            if !rpcs.contains(&rpc.as_str()) {
+                return Err(UserServerError::ConfigError(format!(
+                    "access policy for unknown rpc {}",
+                    rpc
+                )));
+            }
+            policies.insert(rpc.clone(), AccessPolicy::parse(policy)?);
+        }
+        let missing: Vec<&str> = rpcs
+            .iter()
+            .copied()
+            .filter(|rpc| !policies.contains_key(*rpc))
+            .collect();
+        if !missing.is_empty() {
+            return Err(UserServerError::ConfigError(format!(
+                "no access policy for rpc {}",
+                missing.join(", ")
+            )));
+        }
+        Ok(PolicyTable {
+            policies: policies
+                .into_iter()
+                .map(|(rpc, policy)| (format!("/{}/{}", service, rpc), policy))
+                .collect(),
+        })
+    }
+
+    /// 未知的路径按 authenticated 处理
+    pub fn get(&self, path: &str) -> &AccessPolicy {
+        self.policies
+            .get(path)
+            .unwrap_or(&AccessPolicy::Authenticated)
+    }
+}
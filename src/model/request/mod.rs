@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// 分页列表参数
 #[derive(Deserialize)]
@@ -26,3 +26,80 @@ impl ListOption {
         self
     }
 }
+
+/// 授权码请求参数, 登录表单提交时另外带有 email 与 password,
+/// 同意页面提交时只有 consent_ticket 与 consent (approve 或 deny)
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct AuthorizeParams {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub state: String,
+    pub code_challenge: String,
+    pub code_challenge_method: String,
+    pub nonce: String,
+    pub email: String,
+    pub password: String,
+    pub consent_ticket: String,
+    pub consent: String,
+}
+
+/// token 端点参数 (application/x-www-form-urlencoded)
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TokenParams {
+    pub grant_type: String,
+    pub code: String,
+    pub redirect_uri: String,
+    pub code_verifier: String,
+    pub device_code: String,
+    pub username: String,
+    pub password: String,
+    pub refresh_token: String,
+    pub scope: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+/// 上游身份提供方的回调参数, 用户拒绝或上游出错时带有 error
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct FederatedCallbackParams {
+    pub code: String,
+    pub state: String,
+    pub error: String,
+}
+
+/// 动态注册的客户端元数据 (RFC 7591 2), 未知字段忽略
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ClientMetadata {
+    pub client_id: String,
+    pub client_name: String,
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub response_types: Vec<String>,
+    pub token_endpoint_auth_method: String,
+    pub scope: String,
+}
+
+/// 设备授权请求参数 (RFC 8628 3.1)
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct DeviceAuthorizationParams {
+    pub client_id: String,
+    pub client_secret: String,
+    pub scope: String,
+}
+
+/// 用户在浏览器中输入 user_code 并登录, action 为 approve 或 deny
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct DeviceVerificationParams {
+    pub user_code: String,
+    pub email: String,
+    pub password: String,
+    pub action: String,
+}
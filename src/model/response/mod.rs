@@ -34,3 +34,109 @@ pub struct Token {
     pub token: String,
     pub refresh_token: String,
 }
+
+#[derive(Clone, Debug)]
+pub struct ClientToken {
+    pub access_token: String,
+    pub expires_in: u32,
+    pub scope: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct SigningKeys {
+    pub active_kid: String,
+    pub kids: Vec<String>,
+}
+
+/// token 内省结果 (RFC 7662), active 为 false 时其余字段无意义
+#[derive(Clone, Debug, Default)]
+pub struct Introspection {
+    pub active: bool,
+    pub sub: u32,
+    pub email: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub token_type: String,
+    pub scope: String,
+    pub client_id: String,
+    pub jti: String,
+}
+
+/// token 端点的成功响应 (RFC 6749 5.1)
+#[derive(Serialize, Clone, Debug)]
+pub struct OAuthToken {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// 动态注册的客户端信息 (RFC 7591 3.2.1, RFC 7592 3).
+/// client_secret 与 registration_access_token 只在注册时返回一次
+#[derive(Serialize, Clone, Debug)]
+pub struct ClientRegistration {
+    pub client_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    pub client_id_issued_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret_expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration_access_token: Option<String>,
+    pub registration_client_uri: String,
+    pub client_name: String,
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub response_types: Vec<String>,
+    pub token_endpoint_auth_method: String,
+    pub scope: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct InitialAccessToken {
+    pub token: String,
+    pub expires_in: u32,
+}
+
+/// 设备授权响应 (RFC 8628 3.2)
+#[derive(Serialize, Clone, Debug)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: u32,
+    pub interval: u32,
+}
+
+/// OpenID Connect 的标准用户信息, 按 profile 与 email scope 返回相应的字段
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gender: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub birthdate: Option<String>,
+}
+
+/// OpenID Connect 的 id_token
+#[derive(Serialize, Clone, Debug)]
+pub struct IdToken {
+    pub iss: String,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub auth_time: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user_info: UserInfo,
+}
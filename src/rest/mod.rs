@@ -0,0 +1,120 @@
+use crate::config::{Config, DbPool, RedisPool};
+use crate::error::UserServerError;
+use crate::handler::user::rpc_path;
+use crate::middleware::auth;
+use crate::middleware::policy::PolicyTable;
+use crate::util::jwt::Claims;
+use axum::handler::{get, patch, post, put};
+use axum::{AddExtensionLayer, Router};
+use http::header::{self, HeaderMap};
+use http::{Response, StatusCode};
+use hyper::body::Bytes;
+use hyper::Body;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tonic::{Code, Status};
+use tracing::{error, info};
+
+mod openapi;
+mod user;
+
+/// REST 接口共享的连接池, 配置与 gRPC 的访问策略表
+#[derive(Clone)]
+pub struct RestState {
+    pub db_pool: DbPool,
+    pub redis_pool: RedisPool,
+    pub config: Arc<Config>,
+    pub policies: Arc<PolicyTable>,
+}
+
+/// 供 Web 前端与脚本使用的 JSON 接口, 请求与响应的字段与 gRPC 的消息一致
+pub async fn serve(addr: SocketAddr, state: RestState) -> Result<(), hyper::Error> {
+    let app = Router::new()
+        .route("/openapi.json", get(openapi::openapi))
+        .route("/v1/users", get(user::index).post(user::store))
+        .route("/v1/users/:id", get(user::show))
+        .route("/v1/login", post(user::login))
+        .route("/v1/refresh", post(user::refresh_token))
+        .route("/v1/profiles/:id", patch(user::profile_update))
+        .route("/v1/password", put(user::password_update))
+        .layer(AddExtensionLayer::new(state));
+
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await
+}
+
+/// 按对应 RPC 的访问策略校验 token, 策略为 public 且未携带有效 token 时返回 None
+fn authorize(
+    state: &RestState,
+    headers: &HeaderMap,
+    rpc: &str,
+) -> Result<Option<Claims>, UserServerError> {
+    let claims = auth::authenticate(&state.redis_pool, headers);
+    if let Err(err) = state.policies.get(&rpc_path(rpc)).check(claims.as_ref()) {
+        info!("{} rejected: {}", rpc, err);
+        return Err(err);
+    }
+    Ok(claims.ok())
+}
+
+/// 需要调用者身份的接口在策略被改为 public 时仍要求 token
+fn caller(claims: Option<Claims>) -> Result<Claims, UserServerError> {
+    claims.ok_or_else(|| UserServerError::JWTVerifyError("no valid auth token".to_string()))
+}
+
+fn parse<T: DeserializeOwned>(body: &Bytes) -> Result<T, UserServerError> {
+    serde_json::from_slice(body).map_err(|err| UserServerError::ArgumentError(err.to_string()))
+}
+
+/// 与 gRPC 使用相同的错误码, 再按 grpc-gateway 的惯例转换为 HTTP 状态码
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Cancelled => StatusCode::REQUEST_TIMEOUT,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(serde_json::to_vec(body).unwrap_or_default()))
+        .unwrap()
+}
+
+/// 错误响应为 {"code": gRPC 错误码, "message": 说明}, 5xx 不返回内部错误的细节
+fn respond<T: Serialize>(status: StatusCode, result: Result<T, Status>) -> Response<Body> {
+    match result {
+        Ok(body) => json_response(status, &body),
+        Err(err) => {
+            let status = http_status(err.code());
+            let message = if status.is_server_error() {
+                error!("rest api error: {}", err.message());
+                "internal server error"
+            } else {
+                err.message()
+            };
+            json_response(
+                status,
+                &json!({ "code": err.code() as i32, "message": message }),
+            )
+        }
+    }
+}
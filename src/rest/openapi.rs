@@ -0,0 +1,180 @@
+use super::RestState;
+use crate::handler::user::rpc_path;
+use crate::middleware::policy::AccessPolicy;
+use axum::extract::Extension;
+use axum::Json;
+use serde_json::{json, Map, Value};
+
+/// 由 build.rs 根据 proto 消息生成的 schema
+const SCHEMAS: &str = include_str!(concat!(env!("OUT_DIR"), "/openapi_schemas.json"));
+
+/// REST 接口与对应的 RPC, 请求体与响应体为同名的 proto 消息
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    rpc: &'static str,
+    summary: &'static str,
+    request: Option<&'static str>,
+    response: &'static str,
+    status: &'static str,
+}
+
+const OPERATIONS: &[Operation] = &[
+    Operation {
+        method: "get",
+        path: "/v1/users",
+        rpc: "UserIndex",
+        summary: "List users, admins only",
+        request: None,
+        response: "UserIndexResponse",
+        status: "200",
+    },
+    Operation {
+        method: "post",
+        path: "/v1/users",
+        rpc: "UserStore",
+        summary: "Register a user",
+        request: Some("UserStoreRequest"),
+        response: "UserStoreResponse",
+        status: "201",
+    },
+    Operation {
+        method: "get",
+        path: "/v1/users/{id}",
+        rpc: "UserShow",
+        summary: "Show a user, `me` for the caller",
+        request: None,
+        response: "UserShowResponse",
+        status: "200",
+    },
+    Operation {
+        method: "post",
+        path: "/v1/login",
+        rpc: "Login",
+        summary: "Log in with email and password",
+        request: Some("LoginRequest"),
+        response: "LoginResponse",
+        status: "200",
+    },
+    Operation {
+        method: "post",
+        path: "/v1/refresh",
+        rpc: "RefreshToken",
+        summary: "Exchange a refresh token for a new token pair",
+        request: Some("RefreshTokenRequest"),
+        response: "RefreshTokenResponse",
+        status: "200",
+    },
+    Operation {
+        method: "patch",
+        path: "/v1/profiles/{id}",
+        rpc: "UserProfileUpdate",
+        summary: "Update a profile, omitted fields are kept",
+        request: Some("UserProfileUpdateRequest"),
+        response: "UserProfileUpdateResponse",
+        status: "200",
+    },
+    Operation {
+        method: "put",
+        path: "/v1/password",
+        rpc: "PasswordUpdate",
+        summary: "Change the password",
+        request: Some("PasswordUpdateRequest"),
+        response: "PasswordUpdateResponse",
+        status: "200",
+    },
+];
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+/// GET 接口的查询参数取自 RPC 请求消息的字段
+fn parameters(operation: &Operation, schemas: &Map<String, Value>) -> Vec<Value> {
+    let mut parameters = vec![];
+    if operation.path.contains("{id}") {
+        parameters.push(json!({
+            "name": "id",
+            "in": "path",
+            "required": true,
+            "schema": { "type": "string" },
+        }));
+    }
+    let query = format!("{}Request", operation.rpc);
+    if operation.method == "get" {
+        if let Some(Value::Object(properties)) = schemas
+            .get(&query)
+            .and_then(|schema| schema.get("properties"))
+        {
+            for (name, schema) in properties {
+                parameters.push(json!({ "name": name, "in": "query", "schema": schema }));
+            }
+        }
+    }
+    parameters
+}
+
+/// GET /openapi.json: 访问策略按当前配置生成
+pub async fn openapi(Extension(state): Extension<RestState>) -> Json<Value> {
+    let mut schemas: Map<String, Value> = serde_json::from_str(SCHEMAS).unwrap_or_default();
+    schemas.insert(
+        "Error".to_string(),
+        json!({
+            "type": "object",
+            "properties": {
+                "code": { "type": "integer", "description": "gRPC status code" },
+                "message": { "type": "string" },
+            },
+        }),
+    );
+
+    let mut paths = Map::new();
+    for operation in OPERATIONS {
+        let policy = state.policies.get(&rpc_path(operation.rpc));
+        let security = match policy {
+            AccessPolicy::Public => json!([]),
+            _ => json!([{ "bearerAuth": [] }]),
+        };
+        let mut item = json!({
+            "operationId": operation.rpc,
+            "summary": operation.summary,
+            "x-access-policy": policy.to_string(),
+            "security": security,
+            "parameters": parameters(operation, &schemas),
+            "responses": {
+                operation.status: {
+                    "description": "OK",
+                    "content": { "application/json": { "schema": schema_ref(operation.response) } },
+                },
+                "default": {
+                    "description": "Error",
+                    "content": { "application/json": { "schema": schema_ref("Error") } },
+                },
+            },
+        });
+        if let Some(request) = operation.request {
+            item["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema_ref(request) } },
+            });
+        }
+        if let Value::Object(methods) = paths.entry(operation.path).or_insert_with(|| json!({})) {
+            methods.insert(operation.method.to_string(), item);
+        }
+    }
+
+    Json(json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "authorization-server",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    }))
+}
@@ -0,0 +1,150 @@
+use super::{authorize, caller, parse, respond, RestState};
+use crate::service::user as user_service;
+use crate::user_server::{
+    LoginRequest, LoginResponse, PasswordUpdateRequest, PasswordUpdateResponse,
+    RefreshTokenRequest, RefreshTokenResponse, UserIndexRequest, UserIndexResponse,
+    UserProfileUpdateRequest, UserProfileUpdateResponse, UserShowRequest, UserShowResponse,
+    UserStoreRequest, UserStoreResponse,
+};
+use axum::extract::{Extension, Path, Query};
+use http::header::HeaderMap;
+use http::{Response, StatusCode};
+use hyper::body::Bytes;
+use hyper::Body;
+use tonic::Status;
+
+/// GET /v1/users
+pub async fn index(
+    Extension(state): Extension<RestState>,
+    Query(params): Query<UserIndexRequest>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let result = async {
+        let claims = caller(authorize(&state, &headers, "UserIndex")?)?;
+        let page = user_service::user_index(params, claims, state.db_pool.clone()).await?;
+        Ok(UserIndexResponse::from(page))
+    }
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+/// GET /v1/users/:id, id 为 me 时返回调用者自己
+pub async fn show(
+    Extension(state): Extension<RestState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let result = async {
+        let claims = caller(authorize(&state, &headers, "UserShow")?)?;
+        let id = match id.as_str() {
+            "me" => 0,
+            id => id
+                .parse()
+                .map_err(|_| Status::invalid_argument("invalid user id"))?,
+        };
+        let user =
+            user_service::user_show(UserShowRequest { id }, claims, state.db_pool.clone()).await?;
+        Ok(UserShowResponse::from(user))
+    }
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+/// POST /v1/users: 注册
+pub async fn store(
+    Extension(state): Extension<RestState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let result = async {
+        authorize(&state, &headers, "UserStore")?;
+        let params: UserStoreRequest = parse(&body)?;
+        let email = user_service::user_store(params, state.db_pool.clone()).await?;
+        Ok(UserStoreResponse::from(email))
+    }
+    .await;
+    respond(StatusCode::CREATED, result)
+}
+
+/// POST /v1/login
+pub async fn login(
+    Extension(state): Extension<RestState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let result = async {
+        authorize(&state, &headers, "Login")?;
+        let params: LoginRequest = parse(&body)?;
+        let token = user_service::login(
+            params,
+            state.db_pool.clone(),
+            state.redis_pool.clone(),
+            state.config.clone(),
+        )
+        .await?;
+        Ok(LoginResponse::from(token))
+    }
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+/// POST /v1/refresh
+pub async fn refresh_token(
+    Extension(state): Extension<RestState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let result = async {
+        authorize(&state, &headers, "RefreshToken")?;
+        let params: RefreshTokenRequest = parse(&body)?;
+        let token = user_service::refresh_token(
+            params,
+            state.db_pool.clone(),
+            state.redis_pool.clone(),
+            state.config.clone(),
+        )
+        .await?;
+        Ok(RefreshTokenResponse::from(token))
+    }
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+/// PATCH /v1/profiles/:id, 未提交的字段保持不变
+pub async fn profile_update(
+    Extension(state): Extension<RestState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let result = async {
+        let claims = caller(authorize(&state, &headers, "UserProfileUpdate")?)?;
+        let params = UserProfileUpdateRequest {
+            id: id
+                .parse()
+                .map_err(|_| Status::invalid_argument("invalid profile id"))?,
+            ..parse(&body)?
+        };
+        let profile =
+            user_service::user_profile_update(params, claims, state.db_pool.clone()).await?;
+        Ok(UserProfileUpdateResponse::from(profile))
+    }
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+/// PUT /v1/password
+pub async fn password_update(
+    Extension(state): Extension<RestState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let result = async {
+        let claims = caller(authorize(&state, &headers, "PasswordUpdate")?)?;
+        let params: PasswordUpdateRequest = parse(&body)?;
+        let result = user_service::password_update(params, claims, state.db_pool.clone()).await?;
+        Ok(PasswordUpdateResponse::from(result))
+    }
+    .await;
+    respond(StatusCode::OK, result)
+}
@@ -1,3 +1,42 @@
+table! {
+    clients (id) {
+        id -> Unsigned<Integer>,
+        client_id -> Varchar,
+        secret_hash -> Nullable<Varchar>,
+        name -> Varchar,
+        grant_types -> Varchar,
+        scope -> Varchar,
+        redirect_uris -> Text,
+        access_token_ttl -> Nullable<Unsigned<Integer>>,
+        refresh_token_ttl -> Nullable<Unsigned<Integer>>,
+        token_endpoint_auth_method -> Varchar,
+        registration_token_hash -> Nullable<Varchar>,
+        created_at -> Datetime,
+        updated_at -> Datetime,
+    }
+}
+
+table! {
+    consents (id) {
+        id -> Unsigned<Integer>,
+        user_id -> Unsigned<Integer>,
+        client_id -> Varchar,
+        scope -> Varchar,
+        created_at -> Datetime,
+        updated_at -> Datetime,
+    }
+}
+
+table! {
+    federated_identities (id) {
+        id -> Unsigned<Integer>,
+        provider -> Varchar,
+        subject -> Varchar,
+        user_id -> Unsigned<Integer>,
+        created_at -> Datetime,
+    }
+}
+
 table! {
     user_profile (id) {
         id -> Unsigned<Integer>,
@@ -21,4 +60,4 @@ table! {
 }
 
 joinable!(user_profile -> users (user_id));
-allow_tables_to_appear_in_same_query!(user_profile, users,);
+allow_tables_to_appear_in_same_query!(clients, consents, federated_identities, user_profile, users,);
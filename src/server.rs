@@ -2,43 +2,173 @@
 extern crate diesel;
 
 use dotenv::dotenv;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
 use tonic::transport::Server;
+use tower::Layer;
+use tracing::{error, info};
 use user_server::pb_user_server::PbUserServer;
 
 pub mod user_server {
     tonic::include_proto!("user_server");
+    include!(concat!(env!("OUT_DIR"), "/rpc_names.rs"));
 }
 
 mod config;
 mod error;
 mod handler;
+mod middleware;
 mod model;
+mod rest;
+// diesel 1.4 的 table! 与 derive 生成的 impl 会触发 non_local_definitions
+#[allow(non_local_definitions)]
 mod schema;
 mod service;
 mod util;
+mod web;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
     tracing_subscriber::fmt::init();
 
+    const EX_USAGE: i32 = 64;
     let cfg = match config::Config::try_from_env() {
         Ok(config) => config,
         Err(e) => {
             eprintln!("Invalid configuration: {}", e);
-            const EX_USAGE: i32 = 64;
+            std::process::exit(EX_USAGE);
+        }
+    };
+    if let Err(e) = util::password::configure(&cfg) {
+        eprintln!("Invalid configuration: {}", e);
+        std::process::exit(EX_USAGE);
+    }
+    // server calibrate [target_ms]: 推荐 argon2 参数后退出
+    if std::env::args().nth(1).as_deref() == Some("calibrate") {
+        calibrate(&cfg);
+        return Ok(());
+    }
+    util::jwt::configure(&cfg);
+    if let Err(e) = util::jwt::load_keys(&cfg, None) {
+        eprintln!("Invalid configuration: {}", e);
+        std::process::exit(EX_USAGE);
+    }
+    if let Err(e) = service::authenticator::chain(&cfg) {
+        eprintln!("Invalid configuration: {}", e);
+        std::process::exit(EX_USAGE);
+    }
+    let policies = match handler::user::policy_table(&cfg) {
+        Ok(policies) => policies,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
             std::process::exit(EX_USAGE);
         }
     };
     let db_pool: config::DbPool = cfg.build_db_pool().await;
-    let pb_user_server = handler::user::build_server(db_pool.clone()).await;
+    let redis_pool: config::RedisPool = cfg.build_redis_pool().await;
+    let pb_user_server =
+        handler::user::build_server(db_pool.clone(), redis_pool.clone(), Arc::new(cfg.clone()))
+            .await;
+
+    // 收到 SIGHUP 时重新读取配置与密钥目录, 无需重启即可轮换签名密钥
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match config::Config::try_from_env() {
+                Ok(cfg) => {
+                    util::jwt::configure(&cfg);
+                    if let Err(e) = util::password::configure(&cfg) {
+                        error!("failed to reload argon2 parameters: {}", e);
+                    }
+                    match util::jwt::load_keys(&cfg, None) {
+                        Ok(kid) => info!("signing key reloaded, active kid {}", kid),
+                        Err(e) => error!("failed to reload signing key: {}", e),
+                    }
+                }
+                Err(e) => error!("invalid configuration: {}", e),
+            }
+        }
+    });
+
+    println!("HttpServer listening on {}", cfg.http_listen_addr);
+    let http_listen_addr = cfg.http_listen_addr;
+    let web_state = web::WebState {
+        db_pool: db_pool.clone(),
+        redis_pool: redis_pool.clone(),
+        config: Arc::new(cfg.clone()),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = web::serve(http_listen_addr, web_state).await {
+            error!("http server error: {}", e);
+        }
+    });
+
+    println!("RestServer listening on {}", cfg.rest_listen_addr);
+    let rest_listen_addr = cfg.rest_listen_addr;
+    let rest_state = rest::RestState {
+        db_pool: db_pool.clone(),
+        redis_pool: redis_pool.clone(),
+        config: Arc::new(cfg.clone()),
+        policies: Arc::new(policies.clone()),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = rest::serve(rest_listen_addr, rest_state).await {
+            error!("rest server error: {}", e);
+        }
+    });
+
+    // gRPC-Web 请求转换为 gRPC 后再校验访问策略, 拒绝时的响应同样带有 CORS 头
+    let auth = middleware::auth::AuthLayer::new(redis_pool.clone(), policies);
+    let grpc_web =
+        match middleware::grpc_web::enable(&cfg, auth.layer(PbUserServer::new(pb_user_server))) {
+            Ok(service) => service,
+            Err(e) => {
+                eprintln!("Invalid configuration: {}", e);
+                std::process::exit(EX_USAGE);
+            }
+        };
 
     println!("GreeterServer listening on {}", cfg.listen_addr);
 
     Server::builder()
-        .add_service(PbUserServer::new(pb_user_server))
+        .accept_http1(true)
+        .add_service(grpc_web)
         .serve(cfg.listen_addr)
         .await?;
 
     Ok(())
 }
+
+/// 以配置中的变体与并行度为基础, 寻找单次哈希耗时达到目标 (默认 500ms) 的参数
+fn calibrate(cfg: &config::Config) {
+    const EX_USAGE: i32 = 64;
+    let target = match std::env::args().nth(2).map(|ms| ms.parse::<u64>()) {
+        None => 500,
+        Some(Ok(ms)) if ms > 0 => ms,
+        Some(_) => {
+            eprintln!("usage: server calibrate [target_ms]");
+            std::process::exit(EX_USAGE);
+        }
+    };
+    let base = match util::password::HashParams::from_config(cfg) {
+        Ok(base) => base,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(EX_USAGE);
+        }
+    };
+    match util::password::calibrate(base, std::time::Duration::from_millis(target)) {
+        Ok((params, elapsed)) => {
+            println!("# {} ms per hash on this machine", elapsed.as_millis());
+            println!("ARGON2_VARIANT={}", params.variant.as_lowercase_str());
+            println!("ARGON2_MEMORY_COST={}", params.mem_cost);
+            println!("ARGON2_ITERATIONS={}", params.time_cost);
+            println!("ARGON2_PARALLELISM={}", params.lanes);
+        }
+        Err(e) => {
+            eprintln!("calibration failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use crate::config::{Config, DbPool, LdapSettings};
+use crate::error::UserServerError;
+use crate::service::{federation, user as user_service};
+use ldap3::{ldap_escape, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// 校验登录名与密码的后端, 成功时返回本地用户的 id 与邮箱.
+/// 返回 PasswordUnauthorizedError 表示本后端不认可该凭据, 由下一个后端继续尝试
+#[tonic::async_trait]
+pub trait Authenticator: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn authenticate(
+        &self,
+        db_pool: &DbPool,
+        username: &str,
+        password: &str,
+    ) -> Result<(u32, String), UserServerError>;
+}
+
+/// users.hash 中的 argon2 哈希
+pub struct DatabaseAuthenticator;
+
+#[tonic::async_trait]
+impl Authenticator for DatabaseAuthenticator {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn authenticate(
+        &self,
+        db_pool: &DbPool,
+        username: &str,
+        password: &str,
+    ) -> Result<(u32, String), UserServerError> {
+        let conn = &db_pool.get().unwrap();
+        user_service::verify_password(conn, username, password)
+    }
+}
+
+/// LDAP simple bind, 用户在 federated_identities 中以 provider ldap 与 DN 关联本地用户
+pub struct LdapAuthenticator {
+    settings: LdapSettings,
+}
+
+/// 连接与每个 LDAP 操作的超时
+const LDAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl LdapAuthenticator {
+    /// 查找用户并以其 DN bind, 返回 DN 与属性
+    async fn bind(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<SearchEntry>, ldap3::LdapError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(LDAP_TIMEOUT)
+            .set_starttls(self.settings.starttls);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.settings.url).await?;
+        tokio::spawn(async move {
+            if let Err(err) = conn.drive().await {
+                warn!("ldap connection error: {}", err);
+            }
+        });
+
+        if !self.settings.bind_dn.is_empty() {
+            ldap.with_timeout(LDAP_TIMEOUT)
+                .simple_bind(&self.settings.bind_dn, &self.settings.bind_password)
+                .await?
+                .success()?;
+        }
+        let filter = self
+            .settings
+            .search_filter
+            .replace("{username}", &ldap_escape(username));
+        let (entries, _) = ldap
+            .with_timeout(LDAP_TIMEOUT)
+            .search(
+                &self.settings.search_base,
+                Scope::Subtree,
+                &filter,
+                vec![
+                    self.settings.email_attribute.as_str(),
+                    self.settings.nickname_attribute.as_str(),
+                ],
+            )
+            .await?
+            .success()?;
+        // 找不到或找到多个用户时都视为认证失败
+        let entry = match &entries[..] {
+            [entry] => SearchEntry::construct(entry.clone()),
+            _ => return Ok(None),
+        };
+        // rc 49 为 invalidCredentials
+        let result = ldap
+            .with_timeout(LDAP_TIMEOUT)
+            .simple_bind(&entry.dn, password)
+            .await?;
+        let _ = ldap.unbind().await;
+        match result.rc {
+            0 => Ok(Some(entry)),
+            49 => Ok(None),
+            _ => result.success().map(|_| None),
+        }
+    }
+
+    fn attribute<'a>(entry: &'a SearchEntry, name: &str) -> Option<&'a str> {
+        entry
+            .attrs
+            .get(name)
+            .and_then(|values| values.first())
+            .map(String::as_str)
+    }
+}
+
+#[tonic::async_trait]
+impl Authenticator for LdapAuthenticator {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    async fn authenticate(
+        &self,
+        db_pool: &DbPool,
+        username: &str,
+        password: &str,
+    ) -> Result<(u32, String), UserServerError> {
+        // 空密码的 simple bind 是匿名 bind, 总会成功
+        if username.is_empty() || password.is_empty() {
+            return Err(UserServerError::PasswordUnauthorizedError(
+                "密码错误".to_string(),
+            ));
+        }
+        let entry = self
+            .bind(username, password)
+            .await
+            .map_err(|err| UserServerError::UpstreamError(format!("ldap: {}", err)))?
+            .ok_or_else(|| UserServerError::PasswordUnauthorizedError("密码错误".to_string()))?;
+        info!("ldap bind succeeded for {}", entry.dn);
+
+        let conn = &db_pool.get().unwrap();
+        federation::link_or_provision(
+            conn,
+            self.name(),
+            &entry.dn,
+            Self::attribute(&entry, &self.settings.email_attribute),
+            Self::attribute(&entry, &self.settings.nickname_attribute).unwrap_or(""),
+            self.settings.link_existing_users,
+        )
+    }
+}
+
+/// 按配置顺序构造认证后端, 名称未知或缺少 ldap 配置时返回错误
+pub fn chain(config: &Config) -> Result<Vec<Box<dyn Authenticator>>, UserServerError> {
+    config
+        .authenticators
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| -> Result<Box<dyn Authenticator>, UserServerError> {
+            match name {
+                "database" => Ok(Box::new(DatabaseAuthenticator)),
+                "ldap" => match &config.ldap {
+                    Some(settings) => Ok(Box::new(LdapAuthenticator {
+                        settings: settings.clone(),
+                    })),
+                    None => Err(UserServerError::ConfigError(
+                        "ldap authenticator requires [ldap] settings".to_string(),
+                    )),
+                },
+                other => Err(UserServerError::ConfigError(format!(
+                    "unknown authenticator {}",
+                    other
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// 依次尝试各个后端, 第一个认可凭据的后端决定登录的用户.
+/// 后端不可用时继续尝试其余后端, 全部失败时返回最后一个非密码错误
+pub async fn authenticate(
+    db_pool: &DbPool,
+    config: &Config,
+    username: &str,
+    password: &str,
+) -> Result<(u32, String), UserServerError> {
+    authenticate_with(chain(config)?, db_pool, username, password).await
+}
+
+async fn authenticate_with(
+    authenticators: Vec<Box<dyn Authenticator>>,
+    db_pool: &DbPool,
+    username: &str,
+    password: &str,
+) -> Result<(u32, String), UserServerError> {
+    let mut failure = UserServerError::PasswordUnauthorizedError("密码错误".to_string());
+    for authenticator in authenticators {
+        match authenticator
+            .authenticate(db_pool, username, password)
+            .await
+        {
+            Ok(user) => {
+                info!("user {} authenticated by {}", user.0, authenticator.name());
+                return Ok(user);
+            }
+            Err(UserServerError::PasswordUnauthorizedError(_)) => {}
+            Err(err) => {
+                warn!("{} authenticator failed: {}", authenticator.name(), err);
+                failure = err;
+            }
+        }
+    }
+    Err(failure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel::MysqlConnection;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// 返回固定结果并记录调用次数的后端
+    struct Fixed {
+        result: Result<(u32, String), UserServerError>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[tonic::async_trait]
+    impl Authenticator for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        async fn authenticate(
+            &self,
+            _db_pool: &DbPool,
+            _username: &str,
+            _password: &str,
+        ) -> Result<(u32, String), UserServerError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.result.clone()
+        }
+    }
+
+    fn fixed(
+        result: Result<(u32, String), UserServerError>,
+    ) -> (Box<dyn Authenticator>, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let authenticator = Fixed {
+            result,
+            calls: calls.clone(),
+        };
+        (Box::new(authenticator), calls)
+    }
+
+    fn unauthorized() -> UserServerError {
+        UserServerError::PasswordUnauthorizedError("密码错误".to_string())
+    }
+
+    /// 测试中的后端不使用数据库, 连接池不会建立连接
+    fn db_pool() -> DbPool {
+        Pool::builder().build_unchecked(ConnectionManager::<MysqlConnection>::new(""))
+    }
+
+    fn names(config: &Config) -> Result<Vec<&'static str>, UserServerError> {
+        chain(config).map(|chain| chain.iter().map(|item| item.name()).collect())
+    }
+
+    #[test]
+    fn chain_follows_configured_order() {
+        let mut config = Config::for_tests();
+        config.authenticators = " ldap, database ,".to_string();
+        config.ldap = Some(LdapSettings::default());
+        assert_eq!(names(&config).unwrap(), vec!["ldap", "database"]);
+    }
+
+    #[test]
+    fn chain_rejects_unknown_name() {
+        let mut config = Config::for_tests();
+        config.authenticators = "database,kerberos".to_string();
+        assert!(matches!(
+            names(&config),
+            Err(UserServerError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn chain_requires_ldap_settings() {
+        let mut config = Config::for_tests();
+        config.authenticators = "database,ldap".to_string();
+        config.ldap = None;
+        assert!(matches!(
+            names(&config),
+            Err(UserServerError::ConfigError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn first_success_wins() {
+        let (first, first_calls) = fixed(Ok((1, "first@example.com".to_string())));
+        let (second, second_calls) = fixed(Ok((2, "second@example.com".to_string())));
+        let user = authenticate_with(vec![first, second], &db_pool(), "alice", "secret")
+            .await
+            .unwrap();
+        assert_eq!(user.0, 1);
+        assert_eq!(first_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(second_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn falls_through_on_wrong_password() {
+        let (first, _) = fixed(Err(unauthorized()));
+        let (second, _) = fixed(Ok((2, "second@example.com".to_string())));
+        let user = authenticate_with(vec![first, second], &db_pool(), "alice", "secret")
+            .await
+            .unwrap();
+        assert_eq!(user.0, 2);
+    }
+
+    #[tokio::test]
+    async fn reports_unavailable_backend() {
+        let (first, _) = fixed(Err(UserServerError::UpstreamError("ldap down".to_string())));
+        let (second, second_calls) = fixed(Err(unauthorized()));
+        let result = authenticate_with(vec![first, second], &db_pool(), "alice", "secret").await;
+        assert!(matches!(result, Err(UserServerError::UpstreamError(_))));
+        assert_eq!(second_calls.load(Ordering::SeqCst), 1);
+
+        let (first, _) = fixed(Err(unauthorized()));
+        let result = authenticate_with(vec![first], &db_pool(), "alice", "secret").await;
+        assert!(matches!(
+            result,
+            Err(UserServerError::PasswordUnauthorizedError(_))
+        ));
+    }
+}
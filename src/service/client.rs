@@ -0,0 +1,479 @@
+use crate::config::{Config, DbPool, RedisPool, TokenLifetimes};
+use crate::error::UserServerError;
+use crate::model::request::ListOption;
+use crate::model::response::{ClientToken, Page};
+use crate::schema::clients;
+use crate::service::token;
+use crate::user_server::{
+    ClientCredentialsRequest, ClientDestroyRequest, ClientIndexRequest, ClientStoreRequest,
+    ClientUpdateRequest,
+};
+use crate::util::{pagination::*, password, random};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use tracing::info;
+
+/// 客户端可使用的授权类型
+pub const GRANT_TYPES: &[&str] = &[
+    "authorization_code",
+    "password",
+    "refresh_token",
+    "client_credentials",
+    DEVICE_CODE_GRANT,
+];
+
+pub const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// token 端点的客户端认证方式 (RFC 7591 2), none 为公开客户端
+pub const TOKEN_ENDPOINT_AUTH_METHODS: &[&str] =
+    &["client_secret_basic", "client_secret_post", "none"];
+
+#[derive(Queryable, Debug)]
+pub struct Client {
+    pub client_id: String,
+    pub secret_hash: Option<String>,
+    pub name: String,
+    pub grant_types: String,
+    pub scope: String,
+    pub redirect_uris: String,
+    pub access_token_ttl: Option<u32>,
+    pub refresh_token_ttl: Option<u32>,
+    pub token_endpoint_auth_method: String,
+    /// 动态注册的客户端才有, 用于校验 registration access token
+    pub registration_token_hash: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl Client {
+    pub fn allows_grant(&self, grant_type: &str) -> bool {
+        self.grant_types
+            .split_whitespace()
+            .any(|item| item == grant_type)
+    }
+
+    /// redirect_uri 必须与注册的某一项完全一致
+    pub fn allows_redirect_uri(&self, redirect_uri: &str) -> bool {
+        self.redirect_uris
+            .split_whitespace()
+            .any(|item| item == redirect_uri)
+    }
+
+    pub fn is_confidential(&self) -> bool {
+        self.secret_hash.is_some()
+    }
+
+    /// 客户端记录中的有效期优先, 未设置的项使用 [clients.<client_id>] 或全局配置
+    pub fn token_lifetimes(&self, config: &Config) -> TokenLifetimes {
+        let default = config.token_lifetimes(Some(&self.client_id));
+        TokenLifetimes {
+            access_token_ttl: self.access_token_ttl.unwrap_or(default.access_token_ttl),
+            refresh_token_ttl: self.refresh_token_ttl.unwrap_or(default.refresh_token_ttl),
+            ..default
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "clients"]
+pub struct NewClient {
+    pub client_id: String,
+    pub secret_hash: Option<String>,
+    pub name: String,
+    pub grant_types: String,
+    pub scope: String,
+    pub redirect_uris: String,
+    pub access_token_ttl: Option<u32>,
+    pub refresh_token_ttl: Option<u32>,
+    pub token_endpoint_auth_method: String,
+    pub registration_token_hash: Option<String>,
+}
+
+#[derive(AsChangeset, Default, PartialEq)]
+#[table_name = "clients"]
+pub struct ClientChangeset {
+    pub secret_hash: Option<String>,
+    pub name: Option<String>,
+    pub grant_types: Option<String>,
+    pub scope: Option<String>,
+    pub redirect_uris: Option<String>,
+    pub access_token_ttl: Option<u32>,
+    pub refresh_token_ttl: Option<u32>,
+    pub token_endpoint_auth_method: Option<String>,
+}
+
+type ClientColumns = (
+    clients::client_id,
+    clients::secret_hash,
+    clients::name,
+    clients::grant_types,
+    clients::scope,
+    clients::redirect_uris,
+    clients::access_token_ttl,
+    clients::refresh_token_ttl,
+    clients::token_endpoint_auth_method,
+    clients::registration_token_hash,
+    clients::created_at,
+);
+
+const CLIENT_COLUMNS: ClientColumns = (
+    clients::client_id,
+    clients::secret_hash,
+    clients::name,
+    clients::grant_types,
+    clients::scope,
+    clients::redirect_uris,
+    clients::access_token_ttl,
+    clients::refresh_token_ttl,
+    clients::token_endpoint_auth_method,
+    clients::registration_token_hash,
+    clients::created_at,
+);
+
+impl From<ClientIndexRequest> for ListOption {
+    fn from(request: ClientIndexRequest) -> ListOption {
+        let option = ListOption {
+            limit: request.limit,
+            page: request.page,
+        };
+        option.apply_default()
+    }
+}
+
+/// 规范化空格分隔的列表, 授权类型必须是已知的类型
+fn normalize_grant_types(grant_types: &str) -> Result<String, UserServerError> {
+    let mut result: Vec<&str> = vec![];
+    for grant_type in grant_types.split_whitespace() {
+        if !GRANT_TYPES.contains(&grant_type) {
+            return Err(UserServerError::ArgumentError(format!(
+                "unsupported grant type {}",
+                grant_type
+            )));
+        }
+        if !result.contains(&grant_type) {
+            result.push(grant_type);
+        }
+    }
+    Ok(result.join(" "))
+}
+
+/// clients.name 的长度
+const NAME_MAX_CHARS: usize = 60;
+
+/// 管理接口与动态注册共用的校验: 名称长度, client_credentials 只允许机密客户端
+pub fn validate(name: &str, grant_types: &str, confidential: bool) -> Result<(), UserServerError> {
+    if name.chars().count() > NAME_MAX_CHARS {
+        return Err(UserServerError::ArgumentError(format!(
+            "name must be at most {} characters",
+            NAME_MAX_CHARS
+        )));
+    }
+    if grant_types
+        .split_whitespace()
+        .any(|item| item == "client_credentials")
+        && !confidential
+    {
+        return Err(UserServerError::ArgumentError(
+            "client_credentials requires a confidential client".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn normalize_list(list: &str) -> String {
+    list.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn ttl(value: u32) -> Option<u32> {
+    if value == 0 {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// 生成新的 client_secret 与其哈希, 明文只返回给调用者一次
+pub fn new_secret() -> Result<(String, String), UserServerError> {
+    let secret = random::secret_string(43);
+    let hash = password::hash_password(&secret)?;
+    Ok((secret, hash))
+}
+
+/// 查询注册的客户端, 不存在时返回 None
+pub fn find(conn: &PooledConn, client_id: &str) -> Result<Option<Client>, UserServerError> {
+    Ok(clients::table
+        .select(CLIENT_COLUMNS)
+        .filter(clients::client_id.eq(client_id))
+        .get_result::<Client>(conn)
+        .optional()?)
+}
+
+/// 写入新的客户端并返回保存后的记录
+pub fn insert(conn: &PooledConn, new_client: NewClient) -> Result<Client, UserServerError> {
+    diesel::insert_into(clients::table)
+        .values(&new_client)
+        .execute(conn)?;
+    info!("client {} registered", new_client.client_id);
+    find(conn, &new_client.client_id)?
+        .ok_or_else(|| UserServerError::NotFound("client not found".to_string()))
+}
+
+/// 按 changeset 修改客户端, changeset 为空时不执行更新
+pub fn update(
+    conn: &PooledConn,
+    client_id: &str,
+    changeset: ClientChangeset,
+) -> Result<Client, UserServerError> {
+    if changeset != ClientChangeset::default() {
+        diesel::update(clients::table.filter(clients::client_id.eq(client_id)))
+            .set(changeset)
+            .execute(conn)?;
+        info!("client {} updated", client_id);
+    }
+    find(conn, client_id)?.ok_or_else(|| UserServerError::NotFound("client not found".to_string()))
+}
+
+/// 删除客户端, 不存在时返回 NotFound
+pub fn delete(conn: &PooledConn, client_id: &str) -> Result<(), UserServerError> {
+    let deleted =
+        diesel::delete(clients::table.filter(clients::client_id.eq(client_id))).execute(conn)?;
+    if deleted == 0 {
+        return Err(UserServerError::NotFound("client not found".to_string()));
+    }
+    info!("client {} deleted", client_id);
+    Ok(())
+}
+
+pub async fn client_index(
+    params: ClientIndexRequest,
+    db_pool: DbPool,
+) -> Result<Page<Client>, UserServerError> {
+    let list_option = ListOption::from(params);
+    let conn = &db_pool.get().unwrap();
+
+    let result = clients::table
+        .select(CLIENT_COLUMNS)
+        .order(clients::id)
+        .page(list_option.page)
+        .limit(list_option.limit)
+        .paginate::<Client>(conn)?;
+    Ok(result)
+}
+
+/// 注册客户端, confidential 时返回生成的 client_secret
+pub async fn client_store(
+    params: ClientStoreRequest,
+    db_pool: DbPool,
+) -> Result<(Client, String), UserServerError> {
+    let conn = &db_pool.get().unwrap();
+
+    if params.name.is_empty() {
+        return Err(UserServerError::ArgumentError(
+            "name 参数不合法".to_string(),
+        ));
+    }
+    let grant_types = normalize_grant_types(&params.grant_types)?;
+    validate(&params.name, &grant_types, params.confidential)?;
+    let (secret, secret_hash) = if params.confidential {
+        let (secret, hash) = new_secret()?;
+        (secret, Some(hash))
+    } else {
+        ("".to_string(), None)
+    };
+    let token_endpoint_auth_method = if params.confidential {
+        "client_secret_basic"
+    } else {
+        "none"
+    };
+
+    let client = insert(
+        conn,
+        NewClient {
+            client_id: random::random_string(24),
+            secret_hash,
+            name: params.name,
+            grant_types,
+            scope: normalize_list(&params.scope),
+            redirect_uris: normalize_list(&params.redirect_uris),
+            access_token_ttl: ttl(params.access_token_ttl),
+            refresh_token_ttl: ttl(params.refresh_token_ttl),
+            token_endpoint_auth_method: token_endpoint_auth_method.to_string(),
+            registration_token_hash: None,
+        },
+    )?;
+    Ok((client, secret))
+}
+
+/// 修改客户端, 为空或 0 的字段保持不变, reset_secret 时返回新的 client_secret
+pub async fn client_update(
+    params: ClientUpdateRequest,
+    db_pool: DbPool,
+) -> Result<(Client, String), UserServerError> {
+    let conn = &db_pool.get().unwrap();
+
+    let client = find(conn, &params.client_id)?
+        .ok_or_else(|| UserServerError::NotFound("client not found".to_string()))?;
+    let mut changeset = ClientChangeset::default();
+    let mut secret = "".to_string();
+    if params.reset_secret {
+        let (new_secret, hash) = new_secret()?;
+        secret = new_secret;
+        changeset.secret_hash = Some(hash);
+        // 公开客户端生成 client_secret 后成为机密客户端
+        if !client.is_confidential() {
+            changeset.token_endpoint_auth_method = Some("client_secret_basic".to_string());
+        }
+    }
+    if !params.name.is_empty() {
+        changeset.name = Some(params.name);
+    }
+    if !params.grant_types.is_empty() {
+        changeset.grant_types = Some(normalize_grant_types(&params.grant_types)?);
+    }
+    if !params.scope.is_empty() {
+        changeset.scope = Some(normalize_list(&params.scope));
+    }
+    if !params.redirect_uris.is_empty() {
+        changeset.redirect_uris = Some(normalize_list(&params.redirect_uris));
+    }
+    changeset.access_token_ttl = ttl(params.access_token_ttl);
+    changeset.refresh_token_ttl = ttl(params.refresh_token_ttl);
+
+    let confidential = client.secret_hash.is_some() || changeset.secret_hash.is_some();
+    validate(
+        changeset.name.as_ref().unwrap_or(&client.name),
+        changeset
+            .grant_types
+            .as_ref()
+            .unwrap_or(&client.grant_types),
+        confidential,
+    )?;
+
+    let client = update(conn, &params.client_id, changeset)?;
+    Ok((client, secret))
+}
+
+pub async fn client_destroy(
+    params: ClientDestroyRequest,
+    db_pool: DbPool,
+) -> Result<bool, UserServerError> {
+    let conn = &db_pool.get().unwrap();
+
+    delete(conn, &params.client_id)?;
+    Ok(true)
+}
+
+/// 校验客户端的 client_secret, 公开客户端无法通过认证
+pub fn authenticate(
+    conn: &PooledConn,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<Client, UserServerError> {
+    let client = find(conn, client_id)?;
+    let verified = match &client {
+        Some(Client {
+            secret_hash: Some(hash),
+            ..
+        }) => password::verify(hash, client_secret).unwrap_or(false),
+        _ => false,
+    };
+    match client {
+        Some(client) if verified => Ok(client),
+        _ => {
+            info!("client {} authentication failed", client_id);
+            Err(UserServerError::ClientUnauthorizedError(
+                "invalid client".to_string(),
+            ))
+        }
+    }
+}
+
+/// client_credentials 授权: 后端服务以 client_id 与 client_secret 换取 access token
+pub async fn client_credentials(
+    params: ClientCredentialsRequest,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: &Config,
+) -> Result<ClientToken, UserServerError> {
+    let conn = &db_pool.get().unwrap();
+
+    let client = authenticate(conn, &params.client_id, &params.client_secret)?;
+    if !client.allows_grant("client_credentials") {
+        return Err(UserServerError::PermissionDenied(
+            "unauthorized client".to_string(),
+        ));
+    }
+    let scope = token::grant_scope(&client.scope, &params.scope)?;
+    let ttl = client.token_lifetimes(config).access_token_ttl;
+    token::issue_client_token(&redis_pool, config, client.client_id, scope, ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(grant_types: &str) -> Client {
+        Client {
+            client_id: "test-client".to_string(),
+            secret_hash: None,
+            name: "test".to_string(),
+            grant_types: grant_types.to_string(),
+            scope: "".to_string(),
+            redirect_uris: "https://client.example.com/callback".to_string(),
+            access_token_ttl: Some(60),
+            refresh_token_ttl: None,
+            token_endpoint_auth_method: "none".to_string(),
+            registration_token_hash: None,
+            created_at: chrono::Local::now().naive_local(),
+        }
+    }
+
+    #[test]
+    fn allows_only_registered_grants() {
+        let client = client("authorization_code  refresh_token");
+        assert!(client.allows_grant("authorization_code"));
+        assert!(client.allows_grant("refresh_token"));
+        assert!(!client.allows_grant("password"));
+        assert!(!client.allows_grant("refresh"));
+        assert!(!client.allows_grant(""));
+    }
+
+    #[test]
+    fn lifetimes_from_client_record() {
+        let config = Config::for_tests();
+        let lifetimes = client("").token_lifetimes(&config);
+        assert_eq!(lifetimes.access_token_ttl, 60);
+        assert_eq!(lifetimes.refresh_token_ttl, config.refresh_token_ttl);
+    }
+
+    #[test]
+    fn name_length() {
+        let name = "名".repeat(NAME_MAX_CHARS);
+        assert!(validate(&name, "authorization_code", false).is_ok());
+        let name = "名".repeat(NAME_MAX_CHARS + 1);
+        assert!(matches!(
+            validate(&name, "authorization_code", false),
+            Err(UserServerError::ArgumentError(_))
+        ));
+    }
+
+    #[test]
+    fn client_credentials_requires_confidential_client() {
+        assert!(validate("svc", "client_credentials", true).is_ok());
+        assert!(matches!(
+            validate("svc", "authorization_code client_credentials", false),
+            Err(UserServerError::ArgumentError(_))
+        ));
+    }
+
+    #[test]
+    fn grant_types_normalized() {
+        assert_eq!(
+            normalize_grant_types(" password  password refresh_token").unwrap(),
+            "password refresh_token"
+        );
+        assert!(matches!(
+            normalize_grant_types("implicit"),
+            Err(UserServerError::ArgumentError(_))
+        ));
+    }
+}
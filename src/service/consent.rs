@@ -0,0 +1,163 @@
+use crate::config::{DbPool, RedisPool};
+use crate::error::UserServerError;
+use crate::schema::{clients, consents};
+use crate::service::{token, user as user_service};
+use crate::user_server::{ListConsentsRequest, RevokeConsentRequest};
+use crate::util::jwt::Claims;
+use crate::util::pagination::PooledConn;
+use chrono::{Local, NaiveDateTime};
+use diesel::prelude::*;
+use std::collections::HashMap;
+use tracing::info;
+
+/// 用户对客户端的授权, client_name 为查询时客户端的名称
+#[derive(Debug)]
+pub struct Consent {
+    pub client_id: String,
+    pub client_name: String,
+    pub scope: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// 已授权的 scope 是否包含申请的全部 scope
+pub fn covers(granted: &str, requested: &str) -> bool {
+    requested
+        .split_whitespace()
+        .all(|scope| granted.split_whitespace().any(|item| item == scope))
+}
+
+/// 用户已授权给客户端的 scope, 未授权时返回 None
+pub fn granted_scope(
+    conn: &PooledConn,
+    user_id: u32,
+    client_id: &str,
+) -> Result<Option<String>, UserServerError> {
+    Ok(consents::table
+        .select(consents::scope)
+        .filter(consents::user_id.eq(user_id))
+        .filter(consents::client_id.eq(client_id))
+        .get_result::<String>(conn)
+        .optional()?)
+}
+
+/// 记录用户的授权, 与之前授权的 scope 合并
+pub fn grant(
+    conn: &PooledConn,
+    user_id: u32,
+    client_id: &str,
+    scope: &str,
+) -> Result<(), UserServerError> {
+    conn.transaction::<(), UserServerError, _>(|| {
+        let target = consents::table
+            .filter(consents::user_id.eq(user_id))
+            .filter(consents::client_id.eq(client_id));
+        match target
+            .select(consents::scope)
+            .for_update()
+            .get_result::<String>(conn)
+            .optional()?
+        {
+            Some(granted) => {
+                let mut scopes: Vec<&str> = granted.split_whitespace().collect();
+                for item in scope.split_whitespace() {
+                    if !scopes.contains(&item) {
+                        scopes.push(item);
+                    }
+                }
+                diesel::update(target)
+                    .set((
+                        consents::scope.eq(scopes.join(" ")),
+                        consents::updated_at.eq(Local::now().naive_local()),
+                    ))
+                    .execute(conn)?;
+            }
+            None => {
+                diesel::insert_into(consents::table)
+                    .values((
+                        consents::user_id.eq(user_id),
+                        consents::client_id.eq(client_id),
+                        consents::scope.eq(scope),
+                    ))
+                    .execute(conn)?;
+            }
+        }
+        Ok(())
+    })?;
+    info!("user {} granted {} to client {}", user_id, scope, client_id);
+    Ok(())
+}
+
+/// 未指定 user_id 时列出调用者自己的授权
+pub async fn list_consents(
+    params: ListConsentsRequest,
+    caller: Claims,
+    db_pool: DbPool,
+) -> Result<Vec<Consent>, UserServerError> {
+    let conn = &db_pool.get().unwrap();
+
+    let user_id = match params.user_id {
+        0 => caller.sub,
+        id => id,
+    };
+    user_service::check_owner(&caller, user_id)?;
+    let records = consents::table
+        .select((
+            consents::client_id,
+            consents::scope,
+            consents::created_at,
+            consents::updated_at,
+        ))
+        .filter(consents::user_id.eq(user_id))
+        .order(consents::updated_at.desc())
+        .load::<(String, String, NaiveDateTime, NaiveDateTime)>(conn)?;
+    let names: HashMap<String, String> = clients::table
+        .select((clients::client_id, clients::name))
+        .filter(clients::client_id.eq_any(records.iter().map(|record| &record.0)))
+        .load::<(String, String)>(conn)?
+        .into_iter()
+        .collect();
+
+    Ok(records
+        .into_iter()
+        .map(|(client_id, scope, created_at, updated_at)| Consent {
+            client_name: names.get(&client_id).cloned().unwrap_or_default(),
+            client_id,
+            scope,
+            created_at,
+            updated_at,
+        })
+        .collect())
+}
+
+/// 撤销用户对客户端的授权, 同时吊销该授权下签发的 refresh token 家族,
+/// 返回吊销的家族数量
+pub async fn revoke_consent(
+    params: RevokeConsentRequest,
+    caller: Claims,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+) -> Result<u32, UserServerError> {
+    let conn = &db_pool.get().unwrap();
+
+    let user_id = match params.user_id {
+        0 => caller.sub,
+        id => id,
+    };
+    user_service::check_owner(&caller, user_id)?;
+    let deleted = diesel::delete(
+        consents::table
+            .filter(consents::user_id.eq(user_id))
+            .filter(consents::client_id.eq(&params.client_id)),
+    )
+    .execute(conn)?;
+    if deleted == 0 {
+        return Err(UserServerError::NotFound("consent not found".to_string()));
+    }
+    info!(
+        "consent of user {} for client {} revoked",
+        user_id, params.client_id
+    );
+    let revoked = token::revoke_client_families(&redis_pool, user_id, &params.client_id)?;
+    Ok(revoked as u32)
+}
@@ -0,0 +1,282 @@
+use crate::config::{Config, DbPool, RedisPool};
+use crate::error::UserServerError;
+use crate::model::request::{DeviceAuthorizationParams, DeviceVerificationParams, TokenParams};
+use crate::model::response::{DeviceAuthorization, OAuthToken};
+use crate::service::client::{Client, DEVICE_CODE_GRANT};
+use crate::service::{
+    authenticator, client as client_service, consent as consent_service, oauth, token,
+};
+use crate::util::pagination::PooledConn;
+use crate::util::random;
+use chrono::Local;
+use diesel::r2d2::PooledConnection;
+use once_cell::sync::Lazy;
+use rand::{thread_rng, Rng};
+use redis::{Commands, Script};
+use std::collections::HashMap;
+use tracing::info;
+
+/// user_code 使用的字符, 去掉了元音与易混淆的字符 (RFC 8628 6.1)
+const USER_CODE_CHARSET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+/// 连续过快轮询时每次增加的间隔 (秒)
+const SLOW_DOWN_INTERVAL: u32 = 5;
+
+/// 仅当 KEYS[1] 设备仍在等待批准时记录结果 ARGV[1] 并删除 user_code KEYS[2],
+/// 批准时写入用户 ARGV[2] 与邮箱 ARGV[3]. 返回 0 表示设备已过期或已被处理,
+/// 不会重新创建已过期的 device_code
+static VERIFY_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r"
+if redis.call('HGET', KEYS[1], 'status') ~= 'pending' then
+    return 0
+end
+redis.call('DEL', KEYS[2])
+redis.call('HSET', KEYS[1], 'status', ARGV[1])
+if ARGV[1] == 'approved' then
+    redis.call('HSET', KEYS[1], 'user_id', ARGV[2])
+    redis.call('HSET', KEYS[1], 'email', ARGV[3])
+end
+return 1
+",
+    )
+});
+
+fn device_key(device_code: &str) -> String {
+    format!("device_code:{}", device_code)
+}
+
+fn user_code_key(user_code: &str) -> String {
+    format!("user_code:{}", user_code)
+}
+
+fn redis_conn(redis_pool: &RedisPool) -> Result<PooledConnection<redis::Client>, UserServerError> {
+    redis_pool
+        .get()
+        .map_err(|err| UserServerError::RedisError(err.to_string()))
+}
+
+fn new_user_code() -> String {
+    let mut rng = thread_rng();
+    let code: String = (0..8)
+        .map(|_| USER_CODE_CHARSET[rng.gen_range(0..USER_CODE_CHARSET.len())] as char)
+        .collect();
+    format!("{}-{}", &code[..4], &code[4..])
+}
+
+/// 用户输入时忽略大小写, 空格与连字符
+pub fn normalize_user_code(user_code: &str) -> String {
+    let code: String = user_code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if code.len() == 8 {
+        format!("{}-{}", &code[..4], &code[4..])
+    } else {
+        code
+    }
+}
+
+/// 设备端申请 device_code 与 user_code, 状态保存在 Redis 中直至过期
+pub async fn device_authorization(
+    params: DeviceAuthorizationParams,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: &Config,
+) -> Result<DeviceAuthorization, UserServerError> {
+    let conn = &db_pool.get().unwrap();
+    let client = oauth::authenticate_client(conn, &params.client_id, &params.client_secret)?;
+    if !client.allows_grant(DEVICE_CODE_GRANT) {
+        return Err(UserServerError::PermissionDenied(
+            "unauthorized client".to_string(),
+        ));
+    }
+    let scope = token::grant_scope(&client.scope, &params.scope)?;
+
+    let device_code = random::secret_string(43);
+    let user_code = new_user_code();
+    let ttl = config.device_code_ttl as usize;
+    let mut conn = redis_conn(&redis_pool)?;
+    redis::pipe()
+        .atomic()
+        .hset_multiple(
+            device_key(&device_code),
+            &[
+                ("client_id", client.client_id.as_str()),
+                ("scope", scope.as_str()),
+                ("user_code", user_code.as_str()),
+                ("status", "pending"),
+            ],
+        )
+        .hset(
+            device_key(&device_code),
+            "interval",
+            config.device_poll_interval,
+        )
+        .expire(device_key(&device_code), ttl)
+        .set_ex(user_code_key(&user_code), &device_code, ttl)
+        .query::<()>(&mut *conn)?;
+    info!("device code issued to client {}", client.client_id);
+
+    let url = config.public_url.trim_end_matches('/');
+    Ok(DeviceAuthorization {
+        device_code,
+        verification_uri: format!("{}/device", url),
+        verification_uri_complete: format!("{}/device?user_code={}", url, user_code),
+        user_code,
+        expires_in: config.device_code_ttl,
+        interval: config.device_poll_interval,
+    })
+}
+
+/// 根据 user_code 查询待确认的设备授权, 返回发起授权的客户端与申请的 scope
+pub fn pending_device(
+    conn: &PooledConn,
+    redis_pool: &RedisPool,
+    user_code: &str,
+) -> Result<(String, Client, String), UserServerError> {
+    let mut redis = redis_conn(redis_pool)?;
+    let device_code: Option<String> = redis.get(user_code_key(&normalize_user_code(user_code)))?;
+    let device_code = device_code
+        .ok_or_else(|| UserServerError::InvalidGrant("invalid or expired user code".to_string()))?;
+    let device: HashMap<String, String> = redis.hgetall(device_key(&device_code))?;
+    if device.get("status").map(String::as_str) != Some("pending") {
+        return Err(UserServerError::InvalidGrant(
+            "invalid or expired user code".to_string(),
+        ));
+    }
+    let client_id = device.get("client_id").cloned().unwrap_or_default();
+    let client = client_service::find(conn, &client_id)?
+        .ok_or_else(|| UserServerError::ClientUnauthorizedError("unknown client".to_string()))?;
+    let scope = device.get("scope").cloned().unwrap_or_default();
+    Ok((device_code, client, scope))
+}
+
+/// 用户登录后批准或拒绝设备的授权, user_code 随即失效, 批准时记录用户的授权
+pub async fn verify_device(
+    db_pool: &DbPool,
+    redis_pool: &RedisPool,
+    config: &Config,
+    params: &DeviceVerificationParams,
+) -> Result<bool, UserServerError> {
+    let (device_code, client, scope) = {
+        let conn = &db_pool.get().unwrap();
+        pending_device(conn, redis_pool, &params.user_code)?
+    };
+    let (user_id, email) =
+        authenticator::authenticate(db_pool, config, &params.email, &params.password).await?;
+    let approved = params.action == "approve";
+
+    // 认证期间 device_code 可能已过期, 状态的检查与更新须在同一脚本中完成
+    let mut redis = redis_conn(redis_pool)?;
+    let updated: i32 = VERIFY_SCRIPT
+        .key(device_key(&device_code))
+        .key(user_code_key(&normalize_user_code(&params.user_code)))
+        .arg(if approved { "approved" } else { "denied" })
+        .arg(user_id)
+        .arg(email)
+        .invoke(&mut *redis)?;
+    if updated == 0 {
+        return Err(UserServerError::InvalidGrant(
+            "invalid or expired user code".to_string(),
+        ));
+    }
+    if approved {
+        let conn = &db_pool.get().unwrap();
+        consent_service::grant(conn, user_id, &client.client_id, &scope)?;
+    }
+    info!(
+        "device authorization for client {} {} by user {}",
+        client.client_id,
+        if approved { "approved" } else { "denied" },
+        user_id
+    );
+    Ok(approved)
+}
+
+/// 设备端轮询: 等待中返回 authorization_pending, 过快时返回 slow_down 并增加间隔,
+/// 批准后签发 token, device_code 只能兑换一次
+pub async fn poll_device_code(
+    params: TokenParams,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: &Config,
+) -> Result<OAuthToken, UserServerError> {
+    let conn = &db_pool.get().unwrap();
+    let client = oauth::authenticate_client(conn, &params.client_id, &params.client_secret)?;
+    if !client.allows_grant(DEVICE_CODE_GRANT) {
+        return Err(UserServerError::PermissionDenied(
+            "unauthorized client".to_string(),
+        ));
+    }
+
+    let key = device_key(&params.device_code);
+    let mut redis = redis_conn(&redis_pool)?;
+    let device: HashMap<String, String> = redis.hgetall(&key)?;
+    if device.is_empty() {
+        return Err(UserServerError::DeviceAuthorization("expired_token"));
+    }
+    if device.get("client_id") != Some(&client.client_id) {
+        return Err(UserServerError::InvalidGrant(
+            "device code was issued to another client".to_string(),
+        ));
+    }
+
+    let now = Local::now().timestamp();
+    let interval: u32 = device
+        .get("interval")
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(config.device_poll_interval);
+    let last_poll: Option<i64> = device.get("last_poll").and_then(|time| time.parse().ok());
+    redis.hset::<_, _, _, ()>(&key, "last_poll", now)?;
+    if let Some(last_poll) = last_poll {
+        if now - last_poll < interval as i64 {
+            redis.hset::<_, _, _, ()>(&key, "interval", interval + SLOW_DOWN_INTERVAL)?;
+            return Err(UserServerError::DeviceAuthorization("slow_down"));
+        }
+    }
+
+    match device.get("status").map(String::as_str) {
+        Some("approved") => {}
+        Some("denied") => {
+            redis.del::<_, ()>(&key)?;
+            return Err(UserServerError::DeviceAuthorization("access_denied"));
+        }
+        _ => {
+            return Err(UserServerError::DeviceAuthorization(
+                "authorization_pending",
+            ))
+        }
+    }
+    let deleted: i32 = redis.del(&key)?;
+    if deleted == 0 {
+        return Err(UserServerError::DeviceAuthorization("expired_token"));
+    }
+
+    let user_id: u32 = device
+        .get("user_id")
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| UserServerError::InvalidGrant("invalid device code".to_string()))?;
+    let scope = device.get("scope").cloned().unwrap_or_default();
+    let issued = token::issue(
+        &redis_pool,
+        config,
+        user_id,
+        device.get("email").cloned().unwrap_or_default(),
+        Some(&client),
+        scope.clone(),
+    )?;
+    info!(
+        "device code exchanged by client {} for user {}",
+        client.client_id, user_id
+    );
+    Ok(OAuthToken {
+        access_token: issued.token,
+        token_type: "Bearer".to_string(),
+        expires_in: client.token_lifetimes(config).access_token_ttl,
+        refresh_token: Some(issued.refresh_token),
+        scope,
+        id_token: None,
+    })
+}
@@ -0,0 +1,25 @@
+use crate::config::Config;
+use crate::error::UserServerError;
+use crate::model::response::SigningKeys;
+use crate::user_server::RotateSigningKeyRequest;
+use crate::util::jwt;
+use tracing::info;
+
+/// 重新读取配置与密钥目录并切换签发密钥, 之前的密钥只要仍在配置中就继续用于校验.
+/// 指定的 kid 仅在下一次重新加载配置 (如 SIGHUP) 之前有效
+pub async fn rotate_signing_key(
+    params: RotateSigningKeyRequest,
+) -> Result<SigningKeys, UserServerError> {
+    let cfg = Config::try_from_env()?;
+    let kid = if params.kid.is_empty() {
+        None
+    } else {
+        Some(params.kid.as_str())
+    };
+    let active_kid = jwt::load_keys(&cfg, kid)?;
+    info!("签发密钥已切换为 {}", active_kid);
+    Ok(SigningKeys {
+        active_kid,
+        kids: jwt::kids(),
+    })
+}
@@ -1 +1,11 @@
+pub mod authenticator;
+#[allow(non_local_definitions)]
+pub mod client;
+pub mod consent;
+pub mod device;
+pub mod federation;
+pub mod key;
+pub mod oauth;
+pub mod registration;
+pub mod token;
 pub mod user;
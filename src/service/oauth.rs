@@ -0,0 +1,595 @@
+use crate::config::{Config, DbPool, RedisPool};
+use crate::error::UserServerError;
+use crate::model::request::{AuthorizeParams, TokenParams};
+use crate::model::response::{IdToken, OAuthToken, Token, UserInfo};
+use crate::service::client::{self as client_service, Client};
+use crate::service::{authenticator, consent as consent_service, token, user as user_service};
+use crate::user_server::{
+    ClientCredentialsRequest, LoginRequest, RefreshTokenRequest, UserShowRequest,
+};
+use crate::util::jwt::{self, Claims};
+use crate::util::pagination::PooledConn;
+use crate::util::random;
+use chrono::Local;
+use diesel::r2d2::PooledConnection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tracing::info;
+
+/// 保存在 Redis 中的授权码, 兑换时删除. redirect_uri 为授权请求中的原值, 兑换时须一致
+#[derive(Serialize, Deserialize)]
+struct AuthorizationCode {
+    client_id: String,
+    redirect_uri: String,
+    user_id: u32,
+    email: String,
+    scope: String,
+    code_challenge: String,
+    nonce: String,
+    auth_time: usize,
+}
+
+/// 用户登录后等待同意的授权请求, 不保存密码
+#[derive(Serialize, Deserialize)]
+pub struct PendingConsent {
+    pub params: AuthorizeParams,
+    pub user_id: u32,
+    pub email: String,
+    pub auth_time: usize,
+}
+
+/// 登录后的授权结果
+pub enum Authorization {
+    /// 用户已同意过申请的 scope, 直接签发授权码
+    Code(String),
+    /// 需要用户同意, 内容为同意页面提交的票据与申请的 scope
+    ConsentRequired(String, String),
+}
+
+/// 同意页面的有效期 (秒)
+const CONSENT_TICKET_TTL: usize = 600;
+
+fn code_key(code: &str) -> String {
+    format!("authorization_code:{}", code)
+}
+
+fn consent_key(ticket: &str) -> String {
+    format!("consent_ticket:{}", ticket)
+}
+
+fn redis_conn(redis_pool: &RedisPool) -> Result<PooledConnection<redis::Client>, UserServerError> {
+    redis_pool
+        .get()
+        .map_err(|err| UserServerError::RedisError(err.to_string()))
+}
+
+/// 对应 RFC 6749 的 error 参数
+pub fn error_code(error: &UserServerError) -> &'static str {
+    match error {
+        UserServerError::ArgumentError(_) | UserServerError::ValidationError(_) => {
+            "invalid_request"
+        }
+        UserServerError::ClientUnauthorizedError(_) => "invalid_client",
+        UserServerError::InvalidGrant(_) => "invalid_grant",
+        UserServerError::InvalidScope(_) => "invalid_scope",
+        UserServerError::PermissionDenied(_) => "unauthorized_client",
+        UserServerError::PasswordUnauthorizedError(_) | UserServerError::AccessDenied(_) => {
+            "access_denied"
+        }
+        UserServerError::DeviceAuthorization(code) => code,
+        UserServerError::InvalidToken(_) => "invalid_token",
+        UserServerError::InvalidRedirectUri(_) => "invalid_redirect_uri",
+        UserServerError::InvalidClientMetadata(_) => "invalid_client_metadata",
+        UserServerError::UpstreamError(_) => "temporarily_unavailable",
+        _ => "server_error",
+    }
+}
+
+/// 校验 client_id 与 redirect_uri, 失败时不能重定向回客户端.
+/// 未指定 redirect_uri 时使用客户端唯一注册的 redirect_uri
+pub fn check_client(
+    conn: &PooledConn,
+    params: &AuthorizeParams,
+) -> Result<(Client, String), UserServerError> {
+    let client = client_service::find(conn, &params.client_id)?
+        .ok_or_else(|| UserServerError::ClientUnauthorizedError("unknown client".to_string()))?;
+    let redirect_uri = if params.redirect_uri.is_empty() {
+        let mut uris = client.redirect_uris.split_whitespace();
+        match (uris.next(), uris.next()) {
+            (Some(uri), None) => uri.to_string(),
+            _ => {
+                return Err(UserServerError::ArgumentError(
+                    "redirect_uri required".to_string(),
+                ))
+            }
+        }
+    } else {
+        params.redirect_uri.clone()
+    };
+    if !client.allows_redirect_uri(&redirect_uri) {
+        return Err(UserServerError::ArgumentError(
+            "redirect_uri not registered".to_string(),
+        ));
+    }
+    Ok((client, redirect_uri))
+}
+
+/// 校验授权请求, 要求 PKCE (S256), 返回授予的 scope
+pub fn check_request(client: &Client, params: &AuthorizeParams) -> Result<String, UserServerError> {
+    if !client.allows_grant("authorization_code") {
+        return Err(UserServerError::PermissionDenied(
+            "unauthorized client".to_string(),
+        ));
+    }
+    if params.code_challenge_method != "S256" || params.code_challenge.len() != 43 {
+        return Err(UserServerError::ArgumentError(
+            "code_challenge with code_challenge_method S256 required".to_string(),
+        ));
+    }
+    let scope = token::grant_scope(&client.scope, &params.scope)?;
+    if jwt::signs_id_tokens() || !scope.split_whitespace().any(|item| item == "openid") {
+        return Ok(scope);
+    }
+    // 无法签发 id_token 时, 明确申请 openid 的请求被拒绝, 未申请 scope 时不授予 openid
+    if !params.scope.trim().is_empty() {
+        return Err(UserServerError::InvalidScope(
+            "openid requires an asymmetric signing key".to_string(),
+        ));
+    }
+    Ok(scope
+        .split_whitespace()
+        .filter(|item| *item != "openid")
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// 签发一次性的授权码
+fn create_code(
+    redis_pool: &RedisPool,
+    config: &Config,
+    data: &AuthorizationCode,
+) -> Result<String, UserServerError> {
+    let code = random::secret_string(43);
+    let mut conn = redis_conn(redis_pool)?;
+    redis::cmd("SET")
+        .arg(code_key(&code))
+        .arg(serde_json::to_string(data)?)
+        .arg("EX")
+        .arg(config.authorization_code_ttl)
+        .query::<()>(&mut *conn)?;
+    info!(
+        "authorization code issued to client {} for user {}",
+        data.client_id, data.user_id
+    );
+    Ok(code)
+}
+
+/// 用户登录后, 已同意过申请的 scope 时直接签发授权码, 否则保存请求等待用户同意
+pub async fn authorize(
+    db_pool: &DbPool,
+    redis_pool: &RedisPool,
+    config: &Config,
+    params: &AuthorizeParams,
+) -> Result<(Authorization, String), UserServerError> {
+    let (user_id, email) =
+        authenticator::authenticate(db_pool, config, &params.email, &params.password).await?;
+    let conn = &db_pool.get().unwrap();
+    authorize_user(conn, redis_pool, config, params, user_id, email)
+}
+
+/// 用户已通过密码或上游身份提供方认证后继续处理授权请求
+pub fn authorize_user(
+    conn: &PooledConn,
+    redis_pool: &RedisPool,
+    config: &Config,
+    params: &AuthorizeParams,
+    user_id: u32,
+    email: String,
+) -> Result<(Authorization, String), UserServerError> {
+    let (client, redirect_uri) = check_client(conn, params)?;
+    let scope = check_request(&client, params)?;
+    let auth_time = Local::now().timestamp() as usize;
+
+    let granted = consent_service::granted_scope(conn, user_id, &client.client_id)?;
+    if granted.is_some_and(|granted| consent_service::covers(&granted, &scope)) {
+        let code = create_code(
+            redis_pool,
+            config,
+            &AuthorizationCode {
+                client_id: client.client_id,
+                redirect_uri: params.redirect_uri.clone(),
+                user_id,
+                email,
+                scope,
+                code_challenge: params.code_challenge.clone(),
+                nonce: params.nonce.clone(),
+                auth_time,
+            },
+        )?;
+        return Ok((Authorization::Code(code), redirect_uri));
+    }
+
+    let ticket = random::secret_string(43);
+    let pending = PendingConsent {
+        params: AuthorizeParams {
+            password: "".to_string(),
+            ..params.clone()
+        },
+        user_id,
+        email,
+        auth_time,
+    };
+    let mut redis = redis_conn(redis_pool)?;
+    redis::cmd("SET")
+        .arg(consent_key(&ticket))
+        .arg(serde_json::to_string(&pending)?)
+        .arg("EX")
+        .arg(CONSENT_TICKET_TTL)
+        .query::<()>(&mut *redis)?;
+    Ok((Authorization::ConsentRequired(ticket, scope), redirect_uri))
+}
+
+/// 取出等待同意的授权请求, 票据只能使用一次
+pub fn take_consent(
+    redis_pool: &RedisPool,
+    ticket: &str,
+) -> Result<PendingConsent, UserServerError> {
+    let mut redis = redis_conn(redis_pool)?;
+    let (data, _): (Option<String>, i32) = redis::pipe()
+        .atomic()
+        .get(consent_key(ticket))
+        .del(consent_key(ticket))
+        .query(&mut *redis)?;
+    match data {
+        Some(data) => Ok(serde_json::from_str(&data)?),
+        None => Err(UserServerError::InvalidGrant(
+            "invalid or expired consent request".to_string(),
+        )),
+    }
+}
+
+/// 用户同意后记录授权并签发授权码, 客户端配置可能已变化, 重新校验请求
+pub fn approve(
+    conn: &PooledConn,
+    redis_pool: &RedisPool,
+    config: &Config,
+    pending: PendingConsent,
+) -> Result<String, UserServerError> {
+    let (client, _) = check_client(conn, &pending.params)?;
+    let scope = check_request(&client, &pending.params)?;
+    consent_service::grant(conn, pending.user_id, &client.client_id, &scope)?;
+    create_code(
+        redis_pool,
+        config,
+        &AuthorizationCode {
+            client_id: client.client_id,
+            redirect_uri: pending.params.redirect_uri,
+            user_id: pending.user_id,
+            email: pending.email,
+            scope,
+            code_challenge: pending.params.code_challenge,
+            nonce: pending.params.nonce,
+            auth_time: pending.auth_time,
+        },
+    )
+}
+
+/// RFC 7636: BASE64URL(SHA256(code_verifier)) 必须等于 code_challenge
+fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    let valid_verifier = (43..=128).contains(&code_verifier.len())
+        && code_verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c));
+    valid_verifier
+        && base64::encode_config(
+            Sha256::digest(code_verifier.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        ) == code_challenge
+}
+
+/// 机密客户端必须提供 client_secret, 公开客户端不能提供
+pub fn authenticate_client(
+    conn: &PooledConn,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<Client, UserServerError> {
+    if !client_secret.is_empty() {
+        return client_service::authenticate(conn, client_id, client_secret);
+    }
+    match client_service::find(conn, client_id)? {
+        Some(client) if !client.is_confidential() => Ok(client),
+        _ => {
+            info!("client {} authentication failed", client_id);
+            Err(UserServerError::ClientUnauthorizedError(
+                "invalid client".to_string(),
+            ))
+        }
+    }
+}
+
+/// 用授权码换取 token, 授权码无论成功与否都只能使用一次
+pub async fn exchange_code(
+    params: TokenParams,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: &Config,
+) -> Result<OAuthToken, UserServerError> {
+    let conn = &db_pool.get().unwrap();
+    let client = authenticate_client(conn, &params.client_id, &params.client_secret)?;
+    if !client.allows_grant("authorization_code") {
+        return Err(UserServerError::PermissionDenied(
+            "unauthorized client".to_string(),
+        ));
+    }
+
+    let mut redis = redis_conn(&redis_pool)?;
+    let (data, _): (Option<String>, i32) = redis::pipe()
+        .atomic()
+        .get(code_key(&params.code))
+        .del(code_key(&params.code))
+        .query(&mut *redis)?;
+    let data: AuthorizationCode = match data {
+        Some(data) => serde_json::from_str(&data)?,
+        None => {
+            return Err(UserServerError::InvalidGrant(
+                "invalid authorization code".to_string(),
+            ))
+        }
+    };
+    if data.client_id != client.client_id || data.redirect_uri != params.redirect_uri {
+        return Err(UserServerError::InvalidGrant(
+            "authorization code was issued to another client".to_string(),
+        ));
+    }
+    if !verify_pkce(&params.code_verifier, &data.code_challenge) {
+        return Err(UserServerError::InvalidGrant(
+            "code_verifier mismatch".to_string(),
+        ));
+    }
+
+    let issued = token::issue(
+        &redis_pool,
+        config,
+        data.user_id,
+        data.email,
+        Some(&client),
+        data.scope.clone(),
+    )?;
+    info!(
+        "authorization code exchanged by client {} for user {}",
+        client.client_id, data.user_id
+    );
+    let token_info = token::resolve(&redis_pool, &issued.token)?;
+    let expires_in = (token_info.exp - token_info.iat) as u32;
+    let id_token = if token_info.has_scope("openid") {
+        let user_info = user_info(token_info, db_pool).await?;
+        let now = Local::now().timestamp() as usize;
+        Some(jwt::get_id_token(&IdToken {
+            iss: config.jwt_issuer.clone(),
+            aud: client.client_id,
+            exp: now + config.id_token_ttl as usize,
+            iat: now,
+            auth_time: data.auth_time,
+            nonce: Some(data.nonce).filter(|nonce| !nonce.is_empty()),
+            user_info,
+        })?)
+    } else {
+        None
+    };
+    Ok(OAuthToken {
+        access_token: issued.token,
+        token_type: "Bearer".to_string(),
+        expires_in,
+        refresh_token: Some(issued.refresh_token),
+        scope: data.scope,
+        id_token,
+    })
+}
+
+/// 密码授权 (RFC 6749 4.3), 客户端认证与 Login 相同, 由 user_service::login 完成.
+/// 邮箱或密码错误时返回 invalid_grant
+pub async fn password_grant(
+    params: TokenParams,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: Arc<Config>,
+) -> Result<OAuthToken, UserServerError> {
+    if params.client_id.is_empty() {
+        return Err(UserServerError::ClientUnauthorizedError(
+            "invalid client".to_string(),
+        ));
+    }
+    if params.username.is_empty() || params.password.is_empty() {
+        return Err(UserServerError::ArgumentError(
+            "username and password required".to_string(),
+        ));
+    }
+    let issued = user_service::login(
+        LoginRequest {
+            email: params.username,
+            password: params.password,
+            client_id: params.client_id,
+            scope: params.scope,
+            client_secret: params.client_secret,
+        },
+        db_pool,
+        redis_pool.clone(),
+        config.clone(),
+    )
+    .await
+    .map_err(|err| match err {
+        UserServerError::PasswordUnauthorizedError(message) => {
+            UserServerError::InvalidGrant(message)
+        }
+        err => err,
+    })?;
+    oauth_token(&redis_pool, issued)
+}
+
+/// 刷新 token (RFC 6749 6), refresh token 只能由签发时的客户端使用
+pub async fn refresh_grant(
+    params: TokenParams,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: Arc<Config>,
+) -> Result<OAuthToken, UserServerError> {
+    if params.client_id.is_empty() {
+        return Err(UserServerError::ClientUnauthorizedError(
+            "invalid client".to_string(),
+        ));
+    }
+    let invalid_grant = |err| match err {
+        UserServerError::JWTVerifyError(message) => UserServerError::InvalidGrant(message),
+        err => err,
+    };
+    let issued = user_service::refresh_token(
+        RefreshTokenRequest {
+            refresh_token: params.refresh_token,
+            scope: params.scope,
+            client_id: params.client_id,
+            client_secret: params.client_secret,
+        },
+        db_pool,
+        redis_pool.clone(),
+        config.clone(),
+    )
+    .await
+    .map_err(invalid_grant)?;
+    oauth_token(&redis_pool, issued)
+}
+
+/// client_credentials 授权 (RFC 6749 4.4), 只签发 access token
+pub async fn client_credentials_grant(
+    params: TokenParams,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: &Config,
+) -> Result<OAuthToken, UserServerError> {
+    let issued = client_service::client_credentials(
+        ClientCredentialsRequest {
+            client_id: params.client_id,
+            client_secret: params.client_secret,
+            scope: params.scope,
+        },
+        db_pool,
+        redis_pool,
+        config,
+    )
+    .await?;
+    Ok(OAuthToken {
+        access_token: issued.access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: issued.expires_in,
+        refresh_token: None,
+        scope: issued.scope,
+        id_token: None,
+    })
+}
+
+/// 返回新 access token 实际授予的 scope 与有效期
+fn oauth_token(redis_pool: &RedisPool, issued: Token) -> Result<OAuthToken, UserServerError> {
+    let token_info = token::resolve(redis_pool, &issued.token)?;
+    Ok(OAuthToken {
+        access_token: issued.token,
+        token_type: "Bearer".to_string(),
+        expires_in: (token_info.exp - token_info.iat) as u32,
+        refresh_token: Some(issued.refresh_token),
+        scope: token_info.scope,
+        id_token: None,
+    })
+}
+
+/// OpenID Connect 的用户信息, 需要 openid scope, 其余字段由 profile 与 email scope 决定
+pub async fn user_info(token_info: Claims, db_pool: DbPool) -> Result<UserInfo, UserServerError> {
+    if !token_info.has_scope("openid") {
+        return Err(UserServerError::PermissionDenied(
+            "insufficient scope, openid required".to_string(),
+        ));
+    }
+    let profile = token_info.has_scope("profile");
+    let email = token_info.has_scope("email");
+    let user = user_service::user_show(UserShowRequest { id: 0 }, token_info, db_pool).await?;
+    let mut user_info = UserInfo {
+        sub: user.id.to_string(),
+        ..Default::default()
+    };
+    if email {
+        user_info.email = user.email;
+    }
+    if profile {
+        user_info.nickname = user.nickname;
+        user_info.gender = match user.gender {
+            Some(1) => Some("male".to_string()),
+            Some(2) => Some("female".to_string()),
+            _ => None,
+        };
+        user_info.birthdate = user
+            .birthday
+            .map(|birthday| birthday.format("%Y-%m-%d").to_string());
+    }
+    Ok(user_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 7636 附录 B 的示例
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    fn client() -> Client {
+        Client {
+            client_id: "test-client".to_string(),
+            secret_hash: None,
+            name: "test".to_string(),
+            grant_types: "authorization_code".to_string(),
+            scope: "profile".to_string(),
+            redirect_uris: "https://client.example.com/callback".to_string(),
+            access_token_ttl: None,
+            refresh_token_ttl: None,
+            token_endpoint_auth_method: "none".to_string(),
+            registration_token_hash: None,
+            created_at: Local::now().naive_local(),
+        }
+    }
+
+    fn params(method: &str, challenge: &str) -> AuthorizeParams {
+        AuthorizeParams {
+            client_id: "test-client".to_string(),
+            scope: "profile".to_string(),
+            code_challenge: challenge.to_string(),
+            code_challenge_method: method.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pkce_rfc7636_vector() {
+        assert!(verify_pkce(VERIFIER, CHALLENGE));
+        assert!(!verify_pkce(&VERIFIER.replace('d', "e"), CHALLENGE));
+        // 校验码须为 43 至 128 个未保留字符
+        assert!(!verify_pkce(&VERIFIER[..42], CHALLENGE));
+        assert!(!verify_pkce(&format!("{}+", &VERIFIER[1..]), CHALLENGE));
+    }
+
+    #[test]
+    fn request_requires_s256() {
+        assert_eq!(
+            check_request(&client(), &params("S256", CHALLENGE)).unwrap(),
+            "profile"
+        );
+        for params in [
+            params("plain", CHALLENGE),
+            params("", CHALLENGE),
+            params("S256", VERIFIER.get(..42).unwrap()),
+            params("S256", ""),
+        ] {
+            assert!(matches!(
+                check_request(&client(), &params),
+                Err(UserServerError::ArgumentError(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use crate::config::{Config, DbPool, RedisPool};
+use crate::error::UserServerError;
+use crate::model::request::ClientMetadata;
+use crate::model::response::{ClientRegistration, InitialAccessToken};
+use crate::service::client::{
+    self as client_service, Client, ClientChangeset, NewClient, DEVICE_CODE_GRANT,
+    TOKEN_ENDPOINT_AUTH_METHODS,
+};
+use crate::service::token;
+use crate::user_server::InitialAccessTokenRequest;
+use crate::util::pagination::PooledConn;
+use crate::util::random;
+use diesel::r2d2::PooledConnection;
+use sha2::{Digest, Sha256};
+use tracing::info;
+use url::Url;
+
+/// 动态注册可使用的授权类型, password 授权只能由管理员通过 ClientStore 注册
+const REGISTRABLE_GRANT_TYPES: &[&str] = &[
+    "authorization_code",
+    "refresh_token",
+    "client_credentials",
+    DEVICE_CODE_GRANT,
+];
+
+/// 校验并规范化后的客户端元数据
+struct ValidMetadata {
+    name: String,
+    redirect_uris: String,
+    grant_types: String,
+    scope: String,
+    token_endpoint_auth_method: String,
+}
+
+fn initial_token_key(token: &str) -> String {
+    format!("initial_access_token:{}", token)
+}
+
+fn redis_conn(redis_pool: &RedisPool) -> Result<PooledConnection<redis::Client>, UserServerError> {
+    redis_pool
+        .get()
+        .map_err(|err| UserServerError::RedisError(err.to_string()))
+}
+
+/// 数据库中只保存 registration access token 的摘要
+fn token_hash(token: &str) -> String {
+    base64::encode_config(Sha256::digest(token.as_bytes()), base64::URL_SAFE_NO_PAD)
+}
+
+/// https, 回环地址上的 http 与原生应用的私有 scheme (RFC 8252 7), 均不能带 fragment
+fn check_redirect_uri(redirect_uri: &str) -> Result<(), UserServerError> {
+    let url = Url::parse(redirect_uri)
+        .map_err(|_| UserServerError::InvalidRedirectUri(format!("{} is invalid", redirect_uri)))?;
+    if url.fragment().is_some() {
+        return Err(UserServerError::InvalidRedirectUri(format!(
+            "{} must not contain a fragment",
+            redirect_uri
+        )));
+    }
+    let allowed = match url.scheme() {
+        "https" => true,
+        "http" => matches!(
+            url.host_str(),
+            Some("localhost") | Some("127.0.0.1") | Some("[::1]")
+        ),
+        scheme => scheme.contains('.'),
+    };
+    if !allowed {
+        return Err(UserServerError::InvalidRedirectUri(format!(
+            "{} must use https",
+            redirect_uri
+        )));
+    }
+    Ok(())
+}
+
+/// 客户端可申请的 scope 与是否可使用 client_credentials 授权
+struct Allowed<'a> {
+    scope: &'a str,
+    client_credentials: bool,
+}
+
+fn validate(metadata: &ClientMetadata, allowed: Allowed) -> Result<ValidMetadata, UserServerError> {
+    for redirect_uri in &metadata.redirect_uris {
+        check_redirect_uri(redirect_uri)?;
+    }
+
+    let mut grant_types: Vec<&str> = vec![];
+    for grant_type in &metadata.grant_types {
+        if !REGISTRABLE_GRANT_TYPES.contains(&grant_type.as_str()) {
+            return Err(UserServerError::InvalidClientMetadata(format!(
+                "unsupported grant type {}",
+                grant_type
+            )));
+        }
+        if grant_type == "client_credentials" && !allowed.client_credentials {
+            return Err(UserServerError::InvalidClientMetadata(
+                "client_credentials requires an initial access token".to_string(),
+            ));
+        }
+        if !grant_types.contains(&grant_type.as_str()) {
+            grant_types.push(grant_type);
+        }
+    }
+    if grant_types.is_empty() {
+        grant_types.push("authorization_code");
+    }
+    let authorization_code = grant_types.contains(&"authorization_code");
+    if metadata.response_types.iter().any(|item| item != "code")
+        || (!metadata.response_types.is_empty() && !authorization_code)
+    {
+        return Err(UserServerError::InvalidClientMetadata(
+            "response_types must be code with the authorization_code grant".to_string(),
+        ));
+    }
+    if authorization_code && metadata.redirect_uris.is_empty() {
+        return Err(UserServerError::InvalidRedirectUri(
+            "redirect_uris required for authorization_code".to_string(),
+        ));
+    }
+
+    let token_endpoint_auth_method = if metadata.token_endpoint_auth_method.is_empty() {
+        "client_secret_basic"
+    } else {
+        metadata.token_endpoint_auth_method.as_str()
+    };
+    if !TOKEN_ENDPOINT_AUTH_METHODS.contains(&token_endpoint_auth_method) {
+        return Err(UserServerError::InvalidClientMetadata(format!(
+            "unsupported token_endpoint_auth_method {}",
+            token_endpoint_auth_method
+        )));
+    }
+    client_service::validate(
+        metadata.client_name.trim(),
+        &grant_types.join(" "),
+        token_endpoint_auth_method != "none",
+    )
+    .map_err(|err| match err {
+        UserServerError::ArgumentError(message) => UserServerError::InvalidClientMetadata(message),
+        err => err,
+    })?;
+
+    let scope = token::grant_scope(allowed.scope, &metadata.scope)
+        .map_err(|err| UserServerError::InvalidClientMetadata(err.to_string()))?;
+    Ok(ValidMetadata {
+        name: metadata.client_name.trim().to_string(),
+        redirect_uris: client_service::normalize_list(&metadata.redirect_uris.join(" ")),
+        grant_types: grant_types.join(" "),
+        scope,
+        token_endpoint_auth_method: token_endpoint_auth_method.to_string(),
+    })
+}
+
+fn registration(
+    client: Client,
+    client_secret: Option<String>,
+    registration_access_token: Option<String>,
+    config: &Config,
+) -> ClientRegistration {
+    let split = |list: &str| list.split_whitespace().map(String::from).collect();
+    let response_types = if client.allows_grant("authorization_code") {
+        vec!["code".to_string()]
+    } else {
+        vec![]
+    };
+    ClientRegistration {
+        registration_client_uri: format!(
+            "{}/register/{}",
+            config.public_url.trim_end_matches('/'),
+            client.client_id
+        ),
+        client_secret_expires_at: if client.is_confidential() {
+            Some(0)
+        } else {
+            None
+        },
+        client_id: client.client_id,
+        client_secret,
+        client_id_issued_at: client.created_at.timestamp(),
+        registration_access_token,
+        client_name: client.name,
+        redirect_uris: split(&client.redirect_uris),
+        grant_types: split(&client.grant_types),
+        response_types,
+        token_endpoint_auth_method: client.token_endpoint_auth_method,
+        scope: client.scope,
+    }
+}
+
+/// 管理员签发一次性的 initial access token, 用于开放注册受限时注册客户端
+pub async fn initial_access_token(
+    params: InitialAccessTokenRequest,
+    redis_pool: RedisPool,
+    config: &Config,
+) -> Result<InitialAccessToken, UserServerError> {
+    let expires_in = match params.expires_in {
+        0 => config.initial_access_token_ttl,
+        expires_in => expires_in,
+    };
+    let token = random::secret_string(43);
+    let mut conn = redis_conn(&redis_pool)?;
+    redis::cmd("SET")
+        .arg(initial_token_key(&token))
+        .arg(1)
+        .arg("EX")
+        .arg(expires_in)
+        .query::<()>(&mut *conn)?;
+    info!("initial access token issued, expires in {}s", expires_in);
+    Ok(InitialAccessToken { token, expires_in })
+}
+
+/// 校验并消费 initial access token, 未要求时忽略
+fn check_initial_token(
+    redis_pool: &RedisPool,
+    config: &Config,
+    initial_token: Option<&str>,
+) -> Result<(), UserServerError> {
+    let token = match initial_token {
+        Some(token) => token,
+        None if config.registration_requires_initial_token => {
+            return Err(UserServerError::InvalidToken(
+                "initial access token required".to_string(),
+            ))
+        }
+        None => return Ok(()),
+    };
+    let mut conn = redis_conn(redis_pool)?;
+    let deleted: i32 = redis::cmd("DEL")
+        .arg(initial_token_key(token))
+        .query(&mut *conn)?;
+    if deleted == 0 {
+        return Err(UserServerError::InvalidToken(
+            "invalid initial access token".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// 查询动态注册的客户端并校验 registration access token,
+/// 管理员注册的客户端没有 registration access token, 无法通过此接口管理
+fn authorize(
+    conn: &PooledConn,
+    client_id: &str,
+    registration_token: &str,
+) -> Result<Client, UserServerError> {
+    match client_service::find(conn, client_id)? {
+        Some(client)
+            if client.registration_token_hash.as_deref()
+                == Some(token_hash(registration_token).as_str()) =>
+        {
+            Ok(client)
+        }
+        _ => {
+            info!(
+                "registration access token rejected for client {}",
+                client_id
+            );
+            Err(UserServerError::InvalidToken(
+                "invalid registration access token".to_string(),
+            ))
+        }
+    }
+}
+
+/// 动态注册客户端 (RFC 7591), 返回 client_secret 与 registration access token
+pub async fn register(
+    metadata: ClientMetadata,
+    initial_token: Option<String>,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: &Config,
+) -> Result<ClientRegistration, UserServerError> {
+    // 匿名注册只能申请 anonymous_registration_scope 中服务端允许的 scope
+    let anonymous_scope = config
+        .anonymous_registration_scope
+        .split_whitespace()
+        .filter(|scope| config.scope.split_whitespace().any(|item| item == *scope))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let allowed = match initial_token {
+        Some(_) => Allowed {
+            scope: &config.scope,
+            client_credentials: true,
+        },
+        None => Allowed {
+            scope: &anonymous_scope,
+            client_credentials: false,
+        },
+    };
+    let valid = validate(&metadata, allowed)?;
+    check_initial_token(&redis_pool, config, initial_token.as_deref())?;
+    let conn = &db_pool.get().unwrap();
+
+    let (client_secret, secret_hash) = if valid.token_endpoint_auth_method == "none" {
+        (None, None)
+    } else {
+        let (secret, hash) = client_service::new_secret()?;
+        (Some(secret), Some(hash))
+    };
+    let registration_access_token = random::secret_string(43);
+    let client_id = random::random_string(24);
+    let client = client_service::insert(
+        conn,
+        NewClient {
+            name: if valid.name.is_empty() {
+                client_id.clone()
+            } else {
+                valid.name
+            },
+            client_id,
+            secret_hash,
+            grant_types: valid.grant_types,
+            scope: valid.scope,
+            redirect_uris: valid.redirect_uris,
+            access_token_ttl: None,
+            refresh_token_ttl: None,
+            token_endpoint_auth_method: valid.token_endpoint_auth_method,
+            registration_token_hash: Some(token_hash(&registration_access_token)),
+        },
+    )?;
+    Ok(registration(
+        client,
+        client_secret,
+        Some(registration_access_token),
+        config,
+    ))
+}
+
+/// 读取客户端配置 (RFC 7592 2.1)
+pub async fn read(
+    client_id: String,
+    registration_token: String,
+    db_pool: DbPool,
+    config: &Config,
+) -> Result<ClientRegistration, UserServerError> {
+    let conn = &db_pool.get().unwrap();
+    let client = authorize(conn, &client_id, &registration_token)?;
+    Ok(registration(client, None, None, config))
+}
+
+/// 以请求中的元数据整体替换客户端配置 (RFC 7592 2.2).
+/// 公开客户端与机密客户端之间不能互相转换
+pub async fn update(
+    client_id: String,
+    registration_token: String,
+    metadata: ClientMetadata,
+    db_pool: DbPool,
+    config: &Config,
+) -> Result<ClientRegistration, UserServerError> {
+    let conn = &db_pool.get().unwrap();
+    let client = authorize(conn, &client_id, &registration_token)?;
+    if !metadata.client_id.is_empty() && metadata.client_id != client_id {
+        return Err(UserServerError::InvalidClientMetadata(
+            "client_id mismatch".to_string(),
+        ));
+    }
+    // 更新不能扩大已授予的 scope, 也不能新增 client_credentials 授权
+    let valid = validate(
+        &metadata,
+        Allowed {
+            scope: &client.scope,
+            client_credentials: client.allows_grant("client_credentials"),
+        },
+    )?;
+    if (valid.token_endpoint_auth_method == "none") == client.is_confidential() {
+        return Err(UserServerError::InvalidClientMetadata(
+            "token_endpoint_auth_method cannot switch between none and client secret".to_string(),
+        ));
+    }
+
+    let changeset = ClientChangeset {
+        name: Some(if valid.name.is_empty() {
+            client.name
+        } else {
+            valid.name
+        }),
+        grant_types: Some(valid.grant_types),
+        scope: Some(valid.scope),
+        redirect_uris: Some(valid.redirect_uris),
+        token_endpoint_auth_method: Some(valid.token_endpoint_auth_method),
+        ..Default::default()
+    };
+    let client = client_service::update(conn, &client_id, changeset)?;
+    Ok(registration(client, None, None, config))
+}
+
+/// 注销客户端 (RFC 7592 2.3), 已签发的 token 在过期前仍然有效
+pub async fn delete(
+    client_id: String,
+    registration_token: String,
+    db_pool: DbPool,
+) -> Result<(), UserServerError> {
+    let conn = &db_pool.get().unwrap();
+    authorize(conn, &client_id, &registration_token)?;
+    client_service::delete(conn, &client_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANONYMOUS: Allowed = Allowed {
+        scope: "openid profile",
+        client_credentials: false,
+    };
+
+    fn metadata(grant_types: &[&str], scope: &str) -> ClientMetadata {
+        ClientMetadata {
+            redirect_uris: vec!["https://client.example.com/callback".to_string()],
+            grant_types: grant_types.iter().map(|item| item.to_string()).collect(),
+            scope: scope.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn anonymous_scope_limited() {
+        let valid = validate(&metadata(&[], ""), ANONYMOUS).unwrap();
+        assert_eq!(valid.scope, "openid profile");
+        assert!(matches!(
+            validate(&metadata(&[], "openid users:read"), ANONYMOUS),
+            Err(UserServerError::InvalidClientMetadata(_))
+        ));
+    }
+
+    #[test]
+    fn client_credentials_requires_initial_token() {
+        let client_credentials = metadata(&["client_credentials"], "");
+        assert!(matches!(
+            validate(&client_credentials, ANONYMOUS),
+            Err(UserServerError::InvalidClientMetadata(_))
+        ));
+        let trusted = Allowed {
+            scope: "openid profile users:read",
+            client_credentials: true,
+        };
+        let valid = validate(&client_credentials, trusted).unwrap();
+        assert_eq!(valid.grant_types, "client_credentials");
+        assert_eq!(valid.scope, "openid profile users:read");
+    }
+}
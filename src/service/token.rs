@@ -0,0 +1,567 @@
+use crate::config::{Config, DbPool, RedisPool, TokenFormat, TokenLifetimes};
+use crate::error::UserServerError;
+use crate::model::response::{ClientToken, Introspection, Token};
+use crate::service::client::{self as client_service, Client};
+use crate::service::oauth;
+use crate::user_server::{IntrospectTokenRequest, LogoutRequest, RevokeTokenRequest};
+use crate::util::jwt::{self, Claims};
+use crate::util::random;
+use chrono::Local;
+use diesel::r2d2::PooledConnection;
+use once_cell::sync::Lazy;
+use redis::{Commands, Script};
+use tracing::{info, warn};
+
+/// 仅当 KEYS[1] 家族当前的 refresh token 为 ARGV[1] 时替换为 ARGV[2].
+/// 返回 1 表示轮换成功, 0 表示旧 token 被重复使用 (家族随即吊销), -1 表示家族已失效
+static ROTATE_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r"
+local current = redis.call('HGET', KEYS[1], 'current')
+if not current then
+    return -1
+end
+if current ~= ARGV[1] then
+    redis.call('DEL', KEYS[1])
+    return 0
+end
+redis.call('HSET', KEYS[1], 'current', ARGV[2])
+redis.call('EXPIREAT', KEYS[1], ARGV[3])
+return 1
+",
+    )
+});
+
+/// 删除索引中的所有 token 家族及索引本身, 返回删除的家族数,
+/// 与签发时写入索引的操作之间不会交错
+static REVOKE_FAMILIES_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r"
+local fids = redis.call('SMEMBERS', KEYS[1])
+for _, fid in ipairs(fids) do
+    redis.call('DEL', ARGV[1] .. fid)
+end
+redis.call('DEL', KEYS[1])
+return #fids
+",
+    )
+});
+
+const FAMILY_KEY_PREFIX: &str = "refresh_token_family:";
+
+fn family_key(fid: &str) -> String {
+    format!("{}{}", FAMILY_KEY_PREFIX, fid)
+}
+
+/// 用户经由某个客户端登录产生的 token 家族, 撤销授权时一并吊销
+fn client_families_key(sub: u32, client_id: &str) -> String {
+    format!("client_families:{}:{}", sub, client_id)
+}
+
+fn revoked_key(jti: &str) -> String {
+    format!("revoked_token:{}", jti)
+}
+
+fn reference_key(handle: &str) -> String {
+    format!("reference_token:{}", handle)
+}
+
+fn redis_conn(redis_pool: &RedisPool) -> Result<PooledConnection<redis::Client>, UserServerError> {
+    redis_pool
+        .get()
+        .map_err(|err| UserServerError::RedisError(err.to_string()))
+}
+
+/// 按客户端配置的格式生成 token, reference 格式时 claims 保存在 Redis 中直至过期
+fn sign_token(
+    redis_pool: &RedisPool,
+    config: &Config,
+    claims: &Claims,
+) -> Result<String, UserServerError> {
+    match config.token_format(claims.client_id.as_deref()) {
+        TokenFormat::Jwt => jwt::get_token(claims),
+        TokenFormat::Reference => {
+            let token = random::secret_string(43);
+            let mut conn = redis_conn(redis_pool)?;
+            redis::pipe()
+                .atomic()
+                .set(reference_key(&token), serde_json::to_string(claims)?)
+                .expire_at(reference_key(&token), claims.exp)
+                .query::<()>(&mut *conn)?;
+            Ok(token)
+        }
+    }
+}
+
+fn sign(
+    redis_pool: &RedisPool,
+    config: &Config,
+    access: &Claims,
+    refresh: &Claims,
+) -> Result<Token, UserServerError> {
+    Ok(Token {
+        token: sign_token(redis_pool, config, access)?,
+        refresh_token: sign_token(redis_pool, config, refresh)?,
+    })
+}
+
+/// 解析 token: JWT 校验签名与 claims, reference token 从 Redis 中取回 claims
+/// 没有 jti 的旧 token 无法单独吊销, 一律视为无效
+pub fn resolve(redis_pool: &RedisPool, token: &str) -> Result<Claims, UserServerError> {
+    let token_info = if token.split('.').count() == 3 {
+        jwt::verify(token)?
+    } else {
+        let mut conn = redis_conn(redis_pool)?;
+        let data: Option<String> = conn.get(reference_key(token))?;
+        let token_info: Claims = match data {
+            Some(data) => serde_json::from_str(&data)?,
+            None => {
+                return Err(UserServerError::JWTVerifyError(
+                    "unauthorized token".to_string(),
+                ))
+            }
+        };
+        if token_info.exp <= Local::now().timestamp() as usize {
+            return Err(UserServerError::JWTVerifyError(
+                "unauthorized token".to_string(),
+            ));
+        }
+        token_info
+    };
+    if token_info.jti.is_empty() {
+        return Err(UserServerError::JWTVerifyError(
+            "token without jti".to_string(),
+        ));
+    }
+    Ok(token_info)
+}
+
+/// 申请的 scope 必须在 allowed 之内, 未申请时授予 allowed 的全部 scope
+pub fn grant_scope(allowed: &str, requested: &str) -> Result<String, UserServerError> {
+    if requested.trim().is_empty() {
+        return Ok(allowed.split_whitespace().collect::<Vec<_>>().join(" "));
+    }
+    let mut granted: Vec<&str> = vec![];
+    for scope in requested.split_whitespace() {
+        if !allowed.split_whitespace().any(|item| item == scope) {
+            return Err(UserServerError::InvalidScope(scope.to_string()));
+        }
+        if !granted.contains(&scope) {
+            granted.push(scope);
+        }
+    }
+    Ok(granted.join(" "))
+}
+
+/// 同一会话的 access token 与 refresh token, 有效期均不超过会话的最长时间.
+/// 管理员权限在每次签发时按当前配置重新判断
+fn token_pair(
+    lifetimes: &TokenLifetimes,
+    sub: u32,
+    email: String,
+    fid: &str,
+    client_id: Option<String>,
+    auth_time: usize,
+    scope: String,
+) -> (Claims, Claims) {
+    let session_end = auth_time + lifetimes.max_session_lifetime as usize;
+    let mut access = Claims::new(
+        "normal".to_string(),
+        lifetimes.access_token_ttl,
+        sub,
+        email.clone(),
+    );
+    let mut refresh = Claims::new(
+        "refresh".to_string(),
+        lifetimes.refresh_token_ttl,
+        sub,
+        email,
+    );
+    for claims in [&mut access, &mut refresh].iter_mut() {
+        claims.exp = claims.exp.min(session_end);
+        claims.fid = Some(fid.to_string());
+        claims.client_id = client_id.clone();
+        claims.auth_time = Some(auth_time);
+        claims.admin = jwt::is_admin(sub);
+        claims.scope = scope.clone();
+    }
+    (access, refresh)
+}
+
+/// 登录成功后签发 token, 并为 refresh token 创建新的 token 家族.
+/// 授予的 scope 与客户端的有效期保存在家族中, 刷新时沿用
+pub fn issue(
+    redis_pool: &RedisPool,
+    config: &Config,
+    sub: u32,
+    email: String,
+    client: Option<&Client>,
+    scope: String,
+) -> Result<Token, UserServerError> {
+    let fid = random::secret_string(32);
+    let now = Local::now().timestamp() as usize;
+    let lifetimes = match client {
+        Some(client) => client.token_lifetimes(config),
+        None => config.token_lifetimes(None),
+    };
+    let client_id = client.map(|client| client.client_id.clone());
+    let families_key = client_id.as_deref().map(|id| client_families_key(sub, id));
+    let (access, refresh) = token_pair(&lifetimes, sub, email, &fid, client_id, now, scope.clone());
+
+    let mut conn = redis_conn(redis_pool)?;
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .hset(family_key(&fid), "current", &refresh.jti)
+        .hset(family_key(&fid), "user_id", sub)
+        .hset(family_key(&fid), "scope", scope)
+        .hset(
+            family_key(&fid),
+            "access_token_ttl",
+            lifetimes.access_token_ttl,
+        )
+        .hset(
+            family_key(&fid),
+            "refresh_token_ttl",
+            lifetimes.refresh_token_ttl,
+        )
+        .expire_at(family_key(&fid), refresh.exp);
+    // 家族最长存活到会话过期, 索引随每次登录延长
+    if let Some(families_key) = families_key {
+        pipe.sadd(&families_key, &fid)
+            .expire_at(&families_key, now + lifetimes.max_session_lifetime as usize);
+    }
+    pipe.query::<()>(&mut *conn)?;
+
+    sign(redis_pool, config, &access, &refresh)
+}
+
+/// client_credentials 授权的 access token, sub 为 0, 不签发 refresh token
+pub fn issue_client_token(
+    redis_pool: &RedisPool,
+    config: &Config,
+    client_id: String,
+    scope: String,
+    ttl: u32,
+) -> Result<ClientToken, UserServerError> {
+    let mut claims = Claims::new("normal".to_string(), ttl, 0, "".to_string());
+    claims.client_id = Some(client_id);
+    claims.scope = scope;
+    Ok(ClientToken {
+        access_token: sign_token(redis_pool, config, &claims)?,
+        expires_in: ttl,
+        scope: claims.scope,
+    })
+}
+
+/// 用 refresh token 换取新的 token, 旧的 refresh token 随即失效.
+/// 已使用过的 refresh token 再次出现时视为泄露, 吊销整个家族.
+/// 可申请比登录时更小的 scope, 仅作用于新的 access token
+pub fn rotate(
+    redis_pool: &RedisPool,
+    config: &Config,
+    token_info: Claims,
+    scope: &str,
+) -> Result<Token, UserServerError> {
+    let fid = match token_info.fid {
+        Some(fid) => fid,
+        None => {
+            return Err(UserServerError::JWTVerifyError(
+                "invalid refresh token".to_string(),
+            ))
+        }
+    };
+    let default = config.token_lifetimes(token_info.client_id.as_deref());
+    let auth_time = token_info.auth_time.unwrap_or(token_info.iat);
+    if Local::now().timestamp() as usize >= auth_time + default.max_session_lifetime as usize {
+        info!("session {} of user {} expired", fid, token_info.sub);
+        return Err(UserServerError::JWTVerifyError(
+            "session expired".to_string(),
+        ));
+    }
+    let mut conn = redis_conn(redis_pool)?;
+    let (family_scope, access_token_ttl, refresh_token_ttl): (
+        Option<String>,
+        Option<u32>,
+        Option<u32>,
+    ) = redis::cmd("HMGET")
+        .arg(family_key(&fid))
+        .arg(&["scope", "access_token_ttl", "refresh_token_ttl"])
+        .query(&mut *conn)?;
+    let family_scope = family_scope.unwrap_or(token_info.scope);
+    let lifetimes = TokenLifetimes {
+        access_token_ttl: access_token_ttl.unwrap_or(default.access_token_ttl),
+        refresh_token_ttl: refresh_token_ttl.unwrap_or(default.refresh_token_ttl),
+        ..default
+    };
+    let (mut access, refresh) = token_pair(
+        &lifetimes,
+        token_info.sub,
+        token_info.email,
+        &fid,
+        token_info.client_id,
+        auth_time,
+        family_scope.clone(),
+    );
+    access.scope = grant_scope(&family_scope, scope)?;
+
+    let result: i32 = ROTATE_SCRIPT
+        .key(family_key(&fid))
+        .arg(&token_info.jti)
+        .arg(&refresh.jti)
+        .arg(refresh.exp)
+        .invoke(&mut *conn)?;
+    match result {
+        1 => {
+            info!("refresh token family {} rotated", fid);
+            sign(redis_pool, config, &access, &refresh)
+        }
+        0 => {
+            warn!(
+                "refresh token reuse detected, family {} of user {} revoked",
+                fid, token_info.sub
+            );
+            Err(UserServerError::JWTVerifyError(
+                "refresh token revoked".to_string(),
+            ))
+        }
+        _ => Err(UserServerError::JWTVerifyError(
+            "refresh token revoked".to_string(),
+        )),
+    }
+}
+
+/// 将 token 加入吊销名单直至其过期, revoke_family 时同时吊销所属的 token 家族
+pub fn revoke(
+    redis_pool: &RedisPool,
+    token_info: &Claims,
+    revoke_family: bool,
+) -> Result<(), UserServerError> {
+    if token_info.jti.is_empty() {
+        return Err(UserServerError::InvalidToken(
+            "token without jti".to_string(),
+        ));
+    }
+    let mut conn = redis_conn(redis_pool)?;
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    if token_info.exp > Local::now().timestamp() as usize {
+        pipe.set(revoked_key(&token_info.jti), token_info.sub)
+            .expire_at(revoked_key(&token_info.jti), token_info.exp);
+    }
+    if let (true, Some(fid)) = (revoke_family, &token_info.fid) {
+        pipe.del(family_key(fid));
+    }
+    pipe.query::<()>(&mut *conn)?;
+    info!(
+        "token {} of user {} revoked",
+        token_info.jti, token_info.sub
+    );
+    Ok(())
+}
+
+/// 吊销用户经由该客户端登录产生的全部 token 家族, 返回吊销的数量.
+/// 家族删除后, 其下已签发的 access token 在校验时同样失效
+pub fn revoke_client_families(
+    redis_pool: &RedisPool,
+    sub: u32,
+    client_id: &str,
+) -> Result<usize, UserServerError> {
+    let mut conn = redis_conn(redis_pool)?;
+    let revoked: usize = REVOKE_FAMILIES_SCRIPT
+        .key(client_families_key(sub, client_id))
+        .arg(FAMILY_KEY_PREFIX)
+        .invoke(&mut *conn)?;
+    info!(
+        "{} token families of user {} issued to client {} revoked",
+        revoked, sub, client_id
+    );
+    Ok(revoked)
+}
+
+pub fn is_revoked(redis_pool: &RedisPool, token_info: &Claims) -> Result<bool, UserServerError> {
+    let mut conn = redis_conn(redis_pool)?;
+    Ok(conn.exists(revoked_key(&token_info.jti))?)
+}
+
+/// access token 在吊销名单中, 或所属家族已因重复使用或撤销授权被删除时不再有效
+pub fn is_active_access_token(
+    redis_pool: &RedisPool,
+    token_info: &Claims,
+) -> Result<bool, UserServerError> {
+    if is_revoked(redis_pool, token_info)? {
+        return Ok(false);
+    }
+    match &token_info.fid {
+        Some(fid) => {
+            let mut conn = redis_conn(redis_pool)?;
+            Ok(conn.exists(family_key(fid))?)
+        }
+        None => Ok(true),
+    }
+}
+
+/// refresh token 仅在其仍为所属家族当前的 refresh token 时有效
+fn is_current_refresh_token(
+    redis_pool: &RedisPool,
+    token_info: &Claims,
+) -> Result<bool, UserServerError> {
+    let fid = match &token_info.fid {
+        Some(fid) => fid,
+        None => return Ok(false),
+    };
+    let mut conn = redis_conn(redis_pool)?;
+    let current: Option<String> = conn.hget(family_key(fid), "current")?;
+    Ok(current.as_deref() == Some(token_info.jti.as_str()))
+}
+
+/// RFC 7009: 客户端须先通过认证, 且只能吊销签发给自己的 token.
+/// 无效或已过期的 token 同样视为吊销成功.
+/// 吊销 refresh token 时一并吊销其所属家族, 之后该家族无法再换取新的 token
+pub async fn revoke_token(
+    params: RevokeTokenRequest,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+) -> Result<bool, UserServerError> {
+    let client = {
+        let conn = &db_pool.get().unwrap();
+        oauth::authenticate_client(conn, &params.client_id, &params.client_secret)?
+    };
+    let token_info = match resolve(&redis_pool, &params.token) {
+        Ok(token_info) => token_info,
+        Err(err) => {
+            info!("revoke invalid token ({}): {}", params.token_type_hint, err);
+            return Ok(true);
+        }
+    };
+    if token_info.client_id.as_deref() != Some(client.client_id.as_str()) {
+        info!(
+            "client {} tried to revoke token {} of another client",
+            client.client_id, token_info.jti
+        );
+        return Err(UserServerError::PermissionDenied(
+            "token was issued to another client".to_string(),
+        ));
+    }
+    let revoke_family = token_info.grant_type == "refresh";
+    revoke(&redis_pool, &token_info, revoke_family)?;
+    Ok(true)
+}
+
+/// 吊销当前 access token 及其登录会话 (token 家族)
+pub async fn logout(
+    _params: LogoutRequest,
+    token_info: Claims,
+    redis_pool: RedisPool,
+) -> Result<bool, UserServerError> {
+    revoke(&redis_pool, &token_info, true)?;
+    Ok(true)
+}
+
+/// RFC 7662: 调用方须以机密客户端认证.
+/// 校验签名 (reference token 为是否存在), 有效期, 授权类型与吊销状态, 任一不通过时返回 active 为 false
+pub async fn introspect_token(
+    params: IntrospectTokenRequest,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+) -> Result<Introspection, UserServerError> {
+    {
+        let conn = &db_pool.get().unwrap();
+        client_service::authenticate(conn, &params.client_id, &params.client_secret)?;
+    }
+    let token_info = match resolve(&redis_pool, &params.token) {
+        Ok(token_info) => token_info,
+        Err(err) => {
+            info!(
+                "introspect invalid token ({}): {}",
+                params.token_type_hint, err
+            );
+            return Ok(Introspection::default());
+        }
+    };
+    let (token_type, active) = match token_info.grant_type.as_str() {
+        "normal" => (
+            "access_token",
+            is_active_access_token(&redis_pool, &token_info)?,
+        ),
+        "refresh" => (
+            "refresh_token",
+            is_current_refresh_token(&redis_pool, &token_info)?,
+        ),
+        _ => ("", false),
+    };
+    if !active {
+        return Ok(Introspection::default());
+    }
+    Ok(Introspection {
+        active,
+        sub: token_info.sub,
+        email: token_info.email,
+        exp: token_info.exp,
+        iat: token_info.iat,
+        token_type: token_type.to_string(),
+        scope: token_info.scope,
+        client_id: token_info.client_id.unwrap_or_default(),
+        jti: token_info.jti,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::auth;
+    use http::{HeaderMap, HeaderValue};
+
+    fn client() -> Client {
+        Client {
+            client_id: "test-client".to_string(),
+            secret_hash: None,
+            name: "test".to_string(),
+            grant_types: "authorization_code refresh_token".to_string(),
+            scope: "profile".to_string(),
+            redirect_uris: "https://client.example.com/callback".to_string(),
+            access_token_ttl: None,
+            refresh_token_ttl: None,
+            token_endpoint_auth_method: "none".to_string(),
+            registration_token_hash: None,
+            created_at: Local::now().naive_local(),
+        }
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = format!("Bearer {}", token);
+        headers.insert("authorization", HeaderValue::from_str(&value).unwrap());
+        headers
+    }
+
+    /// 需要 Redis, 运行: REDIS_URL=... cargo test -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn access_token_rejected_after_client_families_revoked() {
+        let config = Config::for_tests();
+        jwt::configure(&config);
+        jwt::load_keys(&config, None).unwrap();
+        let redis_pool = config.build_redis_pool().await;
+        let client = client();
+
+        let issued = issue(
+            &redis_pool,
+            &config,
+            42,
+            "".to_string(),
+            Some(&client),
+            "profile".to_string(),
+        )
+        .unwrap();
+        let headers = bearer(&issued.token);
+        assert!(auth::authenticate(&redis_pool, &headers).is_ok());
+
+        assert_eq!(
+            revoke_client_families(&redis_pool, 42, &client.client_id).unwrap(),
+            1
+        );
+        assert!(auth::authenticate(&redis_pool, &headers).is_err());
+        let refresh = resolve(&redis_pool, &issued.refresh_token).unwrap();
+        assert!(!is_current_refresh_token(&redis_pool, &refresh).unwrap());
+    }
+}
@@ -1,17 +1,20 @@
-use crate::config::DbPool;
+use crate::config::{Config, DbPool, RedisPool};
 use crate::error::UserServerError;
 use crate::model::request::ListOption;
 use crate::model::response::{Page, Token};
 use crate::schema::{user_profile, users};
+use crate::service::{authenticator, oauth, token};
 use crate::user_server::{
     LoginRequest, PasswordUpdateRequest, RefreshTokenRequest, UserIndexRequest,
     UserProfileUpdateRequest, UserShowRequest, UserStoreRequest,
 };
-use crate::util::{jwt, pagination::*, password, random};
+use crate::util::jwt::{self, Claims};
+use crate::util::{pagination::*, password, random};
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel::sql_types;
-use tracing::info;
+use std::sync::Arc;
+use tracing::{info, warn};
 
 no_arg_sql_function!(last_insert_id, sql_types::Unsigned<sql_types::Integer>);
 
@@ -42,10 +45,58 @@ impl From<UserIndexRequest> for ListOption {
     }
 }
 
+/// 须为管理员 token, 且用户仍在当前配置的 admin_user_ids 中
+pub fn check_admin(caller: &Claims) -> Result<(), UserServerError> {
+    if caller.admin && jwt::is_admin(caller.sub) {
+        return Ok(());
+    }
+    info!("user {} is not an admin", caller.sub);
+    Err(UserServerError::PermissionDenied(
+        "admin privilege required".to_string(),
+    ))
+}
+
+/// 非管理员只能读写自己的数据, 管理员以当前配置的 admin_user_ids 为准
+pub fn check_owner(caller: &Claims, user_id: u32) -> Result<(), UserServerError> {
+    if caller.sub == user_id || check_admin(caller).is_ok() {
+        return Ok(());
+    }
+    info!(
+        "user {} is not allowed to access user {}",
+        caller.sub, user_id
+    );
+    Err(not_owner())
+}
+
+fn not_owner() -> UserServerError {
+    UserServerError::PermissionDenied("not allowed to access other users".to_string())
+}
+
+/// 按 owner 取出的用户 id 校验记录的归属. 非管理员查询不存在的记录时与无权访问返回相同的错误,
+/// 不泄露其他用户是否存在
+fn check_owned<T>(
+    caller: &Claims,
+    record: Option<T>,
+    owner: impl Fn(&T) -> u32,
+) -> Result<T, UserServerError> {
+    match record {
+        Some(record) => {
+            check_owner(caller, owner(&record))?;
+            Ok(record)
+        }
+        None if check_admin(caller).is_ok() => {
+            Err(UserServerError::NotFound("record not found".to_string()))
+        }
+        None => Err(not_owner()),
+    }
+}
+
 pub async fn user_index(
     params: UserIndexRequest,
+    caller: Claims,
     db_pool: DbPool,
 ) -> Result<Page<User>, UserServerError> {
+    check_admin(&caller)?;
     let list_option = ListOption::from(params.clone());
     info!("params:{:?}", params);
     let conn = &db_pool.get().unwrap();
@@ -79,13 +130,22 @@ pub async fn user_index(
     Ok(result)
 }
 
-pub async fn user_show(params: UserShowRequest, db_pool: DbPool) -> Result<User, UserServerError> {
+/// 未指定 id 时返回调用者自己的数据
+pub async fn user_show(
+    params: UserShowRequest,
+    caller: Claims,
+    db_pool: DbPool,
+) -> Result<User, UserServerError> {
     let conn = &db_pool.get().unwrap();
 
-    let mut query = users::table.left_join(user_profile::table).into_boxed();
-    if params.id != 0 {
-        query = query.filter(users::id.eq(params.id as u32));
-    }
+    let user_id = match params.id {
+        0 => caller.sub,
+        id => id as u32,
+    };
+    check_owner(&caller, user_id)?;
+    let query = users::table
+        .left_join(user_profile::table)
+        .filter(users::id.eq(user_id));
 
     let result = query
         .select((
@@ -133,52 +193,134 @@ pub async fn user_store(
     Ok(params.email)
 }
 
-pub async fn login(params: LoginRequest, db_pool: DbPool) -> Result<Token, UserServerError> {
-    let conn = &db_pool.get().unwrap();
-
+/// 校验本地数据库中的邮箱与密码, 返回用户 id 与邮箱
+pub fn verify_password(
+    conn: &PooledConn,
+    email: &str,
+    password: &str,
+) -> Result<(u32, String), UserServerError> {
     let result = users::table
         .select((users::id, users::email.nullable(), users::hash))
-        .filter(users::email.eq(params.email))
-        .get_result::<(u32, Option<String>, String)>(conn)?;
+        .filter(users::email.eq(email))
+        .get_result::<(u32, Option<String>, String)>(conn)
+        .optional()?;
 
-    info!("login查询的内容:{:?}", result);
-    let login_result = password::verify(&result.2, &params.password)?;
+    let result = match result {
+        Some(result) => result,
+        None => {
+            return Err(UserServerError::PasswordUnauthorizedError(
+                "密码错误".to_string(),
+            ))
+        }
+    };
+    let login_result = password::verify(&result.2, password)?;
     if !login_result {
         return Err(UserServerError::PasswordUnauthorizedError(
             "密码错误".to_string(),
         ));
     }
-    let token = Token {
-        token: jwt::get_token(
-            "normal".to_string(),
-            3600,
-            result.0,
-            result.1.clone().unwrap_or("".to_string()),
-        )?,
-        refresh_token: jwt::get_token(
-            "refresh".to_string(),
-            86400 * 7,
-            result.0,
-            result.1.unwrap_or("".to_string()),
-        )?,
+    if password::needs_rehash(&result.2) {
+        rehash(conn, result.0, &result.2, password);
+    }
+    Ok((result.0, result.1.unwrap_or("".to_string())))
+}
+
+/// 用当前的方式重新哈希密码, 哈希已被修改时不覆盖. 失败只记录日志, 不影响登录
+fn rehash(conn: &PooledConn, user_id: u32, old_hash: &str, password: &str) {
+    let result = password::hash_password(password).and_then(|hash| {
+        Ok(diesel::update(
+            users::table
+                .filter(users::id.eq(user_id))
+                .filter(users::hash.eq(old_hash)),
+        )
+        .set(users::hash.eq(hash))
+        .execute(conn)?)
+    });
+    match result {
+        Ok(_) => info!("password hash of user {} upgraded", user_id),
+        Err(err) => warn!(
+            "failed to upgrade password hash of user {}: {}",
+            user_id, err
+        ),
+    }
+}
+
+/// 指定 client_id 时须通过客户端认证 (机密客户端须提供 client_secret),
+/// 以免冒用其他客户端的 scope 与 token 有效期
+pub async fn login(
+    params: LoginRequest,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: Arc<Config>,
+) -> Result<Token, UserServerError> {
+    let client = if params.client_id.is_empty() {
+        None
+    } else {
+        let conn = &db_pool.get().unwrap();
+        Some(oauth::authenticate_client(
+            conn,
+            &params.client_id,
+            &params.client_secret,
+        )?)
     };
-    let token_info = jwt::verify(&token.token)?;
+    // 客户端须允许 password 授权, scope 以注册信息为准
+    let allowed_scope = match &client {
+        Some(client) if !client.allows_grant("password") => {
+            return Err(UserServerError::PermissionDenied(
+                "unauthorized client".to_string(),
+            ))
+        }
+        Some(client) => client.scope.clone(),
+        None => config.allowed_scope(None).to_string(),
+    };
+    let scope = token::grant_scope(&allowed_scope, &params.scope)?;
+    let (user_id, email) =
+        authenticator::authenticate(&db_pool, &config, &params.email, &params.password).await?;
+    let token = token::issue(&redis_pool, &config, user_id, email, client.as_ref(), scope)?;
+    let token_info = token::resolve(&redis_pool, &token.token)?;
     info!("{:?}", token_info);
     Ok(token)
 }
 
-pub async fn refresh_token(params: RefreshTokenRequest) -> Result<Token, UserServerError> {
-    let token_info = jwt::verify(&params.refresh_token)?;
-    info!("{:?}", token_info);
-    let token = Token {
-        token: jwt::get_token("normal".to_string(), 3600, token_info.sub, token_info.email)?,
-        refresh_token: params.refresh_token,
+/// 签发给客户端的 refresh token 只能由该客户端在认证后使用,
+/// 客户端已删除或不再允许 refresh_token 授权时拒绝
+pub async fn refresh_token(
+    params: RefreshTokenRequest,
+    db_pool: DbPool,
+    redis_pool: RedisPool,
+    config: Arc<Config>,
+) -> Result<Token, UserServerError> {
+    let client_id = match params.client_id.as_str() {
+        "" => None,
+        client_id => {
+            let conn = &db_pool.get().unwrap();
+            let client = oauth::authenticate_client(conn, client_id, &params.client_secret)?;
+            if !client.allows_grant("refresh_token") {
+                return Err(UserServerError::PermissionDenied(
+                    "unauthorized client".to_string(),
+                ));
+            }
+            Some(client.client_id)
+        }
     };
-    Ok(token)
+    let token_info = token::resolve(&redis_pool, &params.refresh_token)?;
+    info!("{:?}", token_info);
+    if token_info.grant_type != "refresh" {
+        return Err(UserServerError::JWTVerifyError(
+            "invalid refresh token".to_string(),
+        ));
+    }
+    if token_info.client_id != client_id {
+        return Err(UserServerError::InvalidGrant(
+            "refresh token was issued to another client".to_string(),
+        ));
+    }
+    token::rotate(&redis_pool, &config, token_info, &params.scope)
 }
 
 pub async fn user_profile_update(
     params: UserProfileUpdateRequest,
+    caller: Claims,
     db_pool: DbPool,
 ) -> Result<UserProfile, UserServerError> {
     let conn = &db_pool.get().unwrap();
@@ -186,7 +328,7 @@ pub async fn user_profile_update(
     if params.id == 0 {
         return Err(UserServerError::ArgumentError("id 参数不合法".to_string()));
     }
-    let mut result = user_profile::table
+    let profile = user_profile::table
         .filter(user_profile::id.eq(params.id as u32))
         .select((
             user_profile::user_id,
@@ -194,7 +336,9 @@ pub async fn user_profile_update(
             user_profile::gender,
             user_profile::birthday.nullable(),
         ))
-        .get_result::<UserProfile>(conn)?;
+        .get_result::<UserProfile>(conn)
+        .optional()?;
+    let mut result = check_owned(&caller, profile, |profile| profile.user_id)?;
 
     let mut update_value = (
         user_profile::nickname.eq(result.nickname.clone()),
@@ -232,22 +376,26 @@ pub async fn user_profile_update(
 
 pub async fn password_update(
     params: PasswordUpdateRequest,
+    caller: Claims,
     db_pool: DbPool,
 ) -> Result<bool, UserServerError> {
     let conn = &db_pool.get().unwrap();
 
-    let result = users::table
+    let user = users::table
         .select((users::id, users::email.nullable(), users::hash))
         .filter(users::email.eq(&params.email))
-        .get_result::<(u32, Option<String>, String)>(conn)?;
+        .get_result::<(u32, Option<String>, String)>(conn)
+        .optional()?;
+    let result = check_owned(&caller, user, |user| user.0)?;
 
-    info!("login查询的内容:{:?}", result);
+    info!("password update for user {}", result.0);
     let verify = password::verify(&result.2, &params.old_password)?;
     if !verify {
         return Err(UserServerError::PasswordUnauthorizedError(
             "密码错误".to_string(),
         ));
     } else {
+        // 新哈希使用当前配置的参数, 旧参数生成的哈希随之被替换
         let new_hash = password::hash_password(&params.new_password)?;
         diesel::update(users::table.filter(users::email.eq(params.email)))
             .set(users::hash.eq(new_hash))
@@ -256,3 +404,42 @@ pub async fn password_update(
     info!("密码修改成功");
     Ok(true)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(sub: u32, admin: bool) -> Claims {
+        jwt::configure(&Config::for_tests());
+        let mut claims = Claims::new("normal".to_string(), 3600, sub, "".to_string());
+        claims.admin = admin;
+        claims
+    }
+
+    #[test]
+    fn owner_or_current_admin() {
+        assert!(check_owner(&claims(2, false), 2).is_ok());
+        assert!(check_owner(&claims(2, false), 3).is_err());
+        assert!(check_owner(&claims(1, true), 3).is_ok());
+        // 已从 admin_user_ids 中移除的用户, token 中的 admin 不再生效
+        assert!(check_owner(&claims(2, true), 3).is_err());
+    }
+
+    #[test]
+    fn missing_record_looks_like_other_users() {
+        let owner = |user_id: &u32| *user_id;
+        assert!(matches!(
+            check_owned(&claims(2, false), None, owner),
+            Err(UserServerError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            check_owned(&claims(2, false), Some(3), owner),
+            Err(UserServerError::PermissionDenied(_))
+        ));
+        assert_eq!(check_owned(&claims(2, false), Some(2), owner).unwrap(), 2);
+        assert!(matches!(
+            check_owned(&claims(1, true), None, owner),
+            Err(UserServerError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,353 @@
+use crate::config::Config;
+use crate::error::UserServerError;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, JwkSet, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use simple_asn1::ASN1Block;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 签名密钥, 非对称算法时附带可公开的 JWK.
+/// 已退役的密钥只保留公钥, 仅用于校验
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub encoding_key: Option<EncodingKey>,
+    pub decoding_key: DecodingKey,
+    pub jwk: Option<Jwk>,
+}
+
+impl SigningKey {
+    pub fn from_secret(
+        kid: &str,
+        algorithm: Algorithm,
+        secret: &str,
+    ) -> Result<SigningKey, UserServerError> {
+        match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Ok(SigningKey {
+                kid: kid.to_string(),
+                algorithm,
+                encoding_key: Some(EncodingKey::from_secret(secret.as_bytes())),
+                decoding_key: DecodingKey::from_secret(secret.as_bytes()),
+                jwk: None,
+            }),
+            _ => Err(UserServerError::KeyError(format!(
+                "{:?} is not a symmetric algorithm",
+                algorithm
+            ))),
+        }
+    }
+
+    pub fn from_pem_file(
+        kid: &str,
+        algorithm: Algorithm,
+        private_key_path: Option<&Path>,
+        public_key_path: &Path,
+    ) -> Result<SigningKey, UserServerError> {
+        let private_pem = match private_key_path {
+            Some(path) => Some(read_file(path)?),
+            None => None,
+        };
+        let public_pem = read_file(public_key_path)?;
+        let (encoding_key, decoding_key) = match algorithm {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => (
+                private_pem
+                    .map(|pem| EncodingKey::from_rsa_pem(&pem))
+                    .transpose()
+                    .map_err(key_error)?,
+                DecodingKey::from_rsa_pem(&public_pem).map_err(key_error)?,
+            ),
+            Algorithm::ES256 | Algorithm::ES384 => (
+                private_pem
+                    .map(|pem| EncodingKey::from_ec_pem(&pem))
+                    .transpose()
+                    .map_err(key_error)?,
+                DecodingKey::from_ec_pem(&public_pem).map_err(key_error)?,
+            ),
+            Algorithm::EdDSA => (
+                private_pem
+                    .map(|pem| EncodingKey::from_ed_pem(&pem))
+                    .transpose()
+                    .map_err(key_error)?,
+                DecodingKey::from_ed_pem(&public_pem).map_err(key_error)?,
+            ),
+            _ => {
+                return Err(UserServerError::KeyError(format!(
+                    "{:?} is not an asymmetric algorithm",
+                    algorithm
+                )))
+            }
+        };
+        Ok(SigningKey {
+            kid: kid.to_string(),
+            algorithm,
+            encoding_key,
+            decoding_key,
+            jwk: Some(public_jwk(kid, algorithm, &public_pem)?),
+        })
+    }
+}
+
+/// 密钥环: 一把用于签发的当前密钥, 以及若干仍可用于校验的密钥, 通过 JWT 头部的 kid 区分
+#[derive(Default)]
+pub struct KeyRing {
+    active_kid: String,
+    default_kid: String,
+    keys: HashMap<String, Arc<SigningKey>>,
+}
+
+impl KeyRing {
+    /// 从配置加载密钥环.
+    ///
+    /// `jwt_keys_dir` 目录下每个 kid 对应 `<kid>.secret` (HMAC 密钥),
+    /// 或 `<kid>.key.pem` + `<kid>.pub.pem` (非对称密钥对); 只有 `<kid>.pub.pem` 的密钥仅用于校验.
+    /// 单独配置的 `jwt_secret_key` / `jwt_private_key_path` 以 `jwt_key_id` 作为 kid 加入密钥环.
+    /// 当前签发密钥为 `active_kid`, 其次为 `jwt_active_kid`, 都未指定时取 kid 最大的可签发密钥.
+    pub fn load(cfg: &Config, active_kid: Option<&str>) -> Result<KeyRing, UserServerError> {
+        let algorithm = cfg.jwt_algorithm.parse::<Algorithm>().map_err(|_| {
+            UserServerError::KeyError(format!("unknown algorithm {}", cfg.jwt_algorithm))
+        })?;
+        let mut keys = HashMap::new();
+
+        if let Some(dir) = &cfg.jwt_keys_dir {
+            for key in load_dir(Path::new(dir), algorithm)? {
+                keys.insert(key.kid.clone(), Arc::new(key));
+            }
+        }
+
+        let kid = cfg.jwt_key_id.as_str();
+        let key = match (&cfg.jwt_private_key_path, &cfg.jwt_public_key_path) {
+            (Some(private_key_path), Some(public_key_path)) => Some(SigningKey::from_pem_file(
+                kid,
+                algorithm,
+                Some(Path::new(private_key_path)),
+                Path::new(public_key_path),
+            )?),
+            _ => match &cfg.jwt_secret_key {
+                Some(secret) => Some(SigningKey::from_secret(kid, algorithm, secret)?),
+                None => None,
+            },
+        };
+        if let Some(key) = key {
+            keys.insert(key.kid.clone(), Arc::new(key));
+        }
+
+        let active_kid = match active_kid.or(cfg.jwt_active_kid.as_deref()) {
+            Some(kid) => kid.to_string(),
+            None => keys
+                .values()
+                .filter(|key| key.encoding_key.is_some())
+                .map(|key| key.kid.clone())
+                .max()
+                .ok_or_else(|| {
+                    UserServerError::KeyError(
+                        "未设置 JWT_SECRET_KEY, JWT_PRIVATE_KEY_PATH/JWT_PUBLIC_KEY_PATH 或 JWT_KEYS_DIR"
+                            .to_string(),
+                    )
+                })?,
+        };
+        match keys.get(&active_kid) {
+            Some(key) if key.encoding_key.is_some() => {}
+            _ => {
+                return Err(UserServerError::KeyError(format!(
+                    "no signing key for kid {}",
+                    active_kid
+                )))
+            }
+        }
+
+        Ok(KeyRing {
+            active_kid,
+            default_kid: kid.to_string(),
+            keys,
+        })
+    }
+
+    pub fn active(&self) -> Option<Arc<SigningKey>> {
+        self.keys.get(&self.active_kid).cloned()
+    }
+
+    /// 按 kid 查找校验密钥, 未携带 kid 的 token 使用 `jwt_key_id` 对应的密钥
+    pub fn find(&self, kid: Option<&str>) -> Option<Arc<SigningKey>> {
+        self.keys
+            .get(kid.unwrap_or(self.default_kid.as_str()))
+            .cloned()
+    }
+
+    pub fn kids(&self) -> Vec<String> {
+        let mut kids: Vec<String> = self.keys.keys().cloned().collect();
+        kids.sort();
+        kids
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keys
+                .values()
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct KeyFiles {
+    secret: Option<PathBuf>,
+    private_key: Option<PathBuf>,
+    public_key: Option<PathBuf>,
+}
+
+fn load_dir(dir: &Path, algorithm: Algorithm) -> Result<Vec<SigningKey>, UserServerError> {
+    let mut files: BTreeMap<String, KeyFiles> = BTreeMap::new();
+    let entries = fs::read_dir(dir)
+        .map_err(|err| UserServerError::KeyError(format!("{} : {}", dir.display(), err)))?;
+    for entry in entries {
+        let path = entry.map_err(key_error)?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if let Some(kid) = name.strip_suffix(".secret") {
+            files.entry(kid.to_string()).or_default().secret = Some(path);
+        } else if let Some(kid) = name.strip_suffix(".key.pem") {
+            files.entry(kid.to_string()).or_default().private_key = Some(path);
+        } else if let Some(kid) = name.strip_suffix(".pub.pem") {
+            files.entry(kid.to_string()).or_default().public_key = Some(path);
+        }
+    }
+
+    let mut keys = vec![];
+    for (kid, files) in files {
+        let key = match files {
+            KeyFiles {
+                secret: Some(secret),
+                ..
+            } => {
+                let secret = String::from_utf8(read_file(&secret)?).map_err(key_error)?;
+                SigningKey::from_secret(&kid, algorithm, secret.trim())?
+            }
+            KeyFiles {
+                public_key: Some(public_key),
+                private_key,
+                ..
+            } => SigningKey::from_pem_file(&kid, algorithm, private_key.as_deref(), &public_key)?,
+            _ => {
+                return Err(UserServerError::KeyError(format!(
+                    "missing public key for kid {}",
+                    kid
+                )))
+            }
+        };
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+fn key_error<E: ToString>(err: E) -> UserServerError {
+    UserServerError::KeyError(err.to_string())
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, UserServerError> {
+    fs::read(path).map_err(|err| UserServerError::KeyError(format!("{} : {}", path.display(), err)))
+}
+
+/// 将 PEM 格式的公钥转换为 JWK (RFC 7517)
+fn public_jwk(kid: &str, algorithm: Algorithm, public_pem: &[u8]) -> Result<Jwk, UserServerError> {
+    let pem = pem::parse(public_pem).map_err(key_error)?;
+    let key = match pem.tag.as_str() {
+        "PUBLIC KEY" => subject_public_key(&pem.contents)?,
+        "RSA PUBLIC KEY" => pem.contents,
+        tag => {
+            return Err(UserServerError::KeyError(format!(
+                "unsupported public key type : {}",
+                tag
+            )))
+        }
+    };
+    let parameters = match algorithm {
+        Algorithm::ES256 | Algorithm::ES384 => {
+            let curve = match algorithm {
+                Algorithm::ES256 => EllipticCurve::P256,
+                _ => EllipticCurve::P384,
+            };
+            // 未压缩的椭圆曲线点: 0x04 || x || y
+            if key.len() % 2 != 1 || key[0] != 0x04 {
+                return Err(UserServerError::KeyError(
+                    "unsupported elliptic curve point".to_string(),
+                ));
+            }
+            let (x, y) = key[1..].split_at(key.len() / 2);
+            AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve,
+                x: base64_url(x),
+                y: base64_url(y),
+            })
+        }
+        Algorithm::EdDSA => AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x: base64_url(&key),
+        }),
+        _ => {
+            let (n, e) = rsa_public_key(&key)?;
+            AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: base64_url(&n),
+                e: base64_url(&e),
+            })
+        }
+    };
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            algorithm: Some(algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    })
+}
+
+/// 从 SubjectPublicKeyInfo 中取出公钥本体
+fn subject_public_key(der: &[u8]) -> Result<Vec<u8>, UserServerError> {
+    let blocks = simple_asn1::from_der(der).map_err(key_error)?;
+    if let Some(ASN1Block::Sequence(_, items)) = blocks.first() {
+        if let Some(ASN1Block::BitString(_, _, key)) = items.get(1) {
+            return Ok(key.clone());
+        }
+    }
+    Err(UserServerError::KeyError(
+        "invalid subject public key info".to_string(),
+    ))
+}
+
+/// 从 PKCS#1 RSAPublicKey 中取出 modulus 与 exponent
+fn rsa_public_key(der: &[u8]) -> Result<(Vec<u8>, Vec<u8>), UserServerError> {
+    let blocks = simple_asn1::from_der(der).map_err(key_error)?;
+    if let Some(ASN1Block::Sequence(_, items)) = blocks.first() {
+        if let (Some(ASN1Block::Integer(_, n)), Some(ASN1Block::Integer(_, e))) =
+            (items.first(), items.get(1))
+        {
+            return Ok((n.to_bytes_be().1, e.to_bytes_be().1));
+        }
+    }
+    Err(UserServerError::KeyError(
+        "invalid rsa public key".to_string(),
+    ))
+}
+
+fn base64_url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
@@ -1,46 +1,146 @@
+use crate::config::Config;
 use crate::error::UserServerError;
+use crate::util::jwk::KeyRing;
+use crate::util::random;
 use chrono::Local;
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, Header, Validation};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use tracing::info;
 
-static JWT_SECRET_KEY: Lazy<String> =
-    Lazy::new(|| std::env::var("JWT_SECRET_KEY").expect("未设置 JWT_SECRET_KEY"));
+static KEY_RING: Lazy<RwLock<KeyRing>> = Lazy::new(|| RwLock::new(KeyRing::default()));
+static SETTINGS: Lazy<RwLock<JwtSettings>> = Lazy::new(|| RwLock::new(JwtSettings::default()));
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Default)]
+struct JwtSettings {
+    issuer: String,
+    audiences: Vec<String>,
+    leeway: u64,
+    admin_user_ids: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub grant_type: String,
     pub email: String,
+    pub iss: String,
     pub sub: u32,
+    pub aud: Vec<String>,
     pub exp: usize,
+    pub nbf: usize,
     pub iat: usize,
+    pub jti: String,
+    /// token 所属的 token 家族, 即同一次登录会话
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// 登录时间, 用于限制会话的最长时间
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<usize>,
+    /// 签发时根据 admin_user_ids 判断, 可读写其他用户的数据
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub admin: bool,
+    /// 空格分隔的 scope
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub scope: String,
 }
 
 impl Claims {
-    fn new(grant_type: String, exp: u32, sub: u32, email: String) -> Claims {
+    pub fn new(grant_type: String, exp: u32, sub: u32, email: String) -> Claims {
         let now = Local::now().timestamp() as usize;
+        let settings = SETTINGS.read().unwrap();
         Claims {
             grant_type,
             email,
+            iss: settings.issuer.clone(),
             sub,
+            aud: settings.audiences.clone(),
             exp: now + exp as usize,
+            nbf: now,
             iat: now,
+            jti: random::secret_string(32),
+            fid: None,
+            client_id: None,
+            auth_time: None,
+            admin: false,
+            scope: "".to_string(),
         }
     }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope
+            .split_whitespace()
+            .any(|granted| granted == scope)
+    }
 }
 
-pub fn get_token(
-    grant_type: String,
-    exp: u32,
-    sub: u32,
-    email: String,
-) -> Result<String, UserServerError> {
-    let claims = Claims::new(grant_type, exp, sub, email);
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(JWT_SECRET_KEY.as_bytes()),
+/// 根据配置设置 iss, aud, 时钟偏差与管理员, 需在签发或校验 token 之前调用,
+/// 收到 SIGHUP 时与密钥一同重新加载
+pub fn configure(cfg: &Config) {
+    *SETTINGS.write().unwrap() = JwtSettings {
+        issuer: cfg.jwt_issuer.clone(),
+        audiences: cfg
+            .jwt_audience
+            .split(',')
+            .map(|aud| aud.trim().to_string())
+            .filter(|aud| !aud.is_empty())
+            .collect(),
+        leeway: cfg.jwt_leeway,
+        admin_user_ids: cfg
+            .admin_user_ids
+            .split(',')
+            .filter_map(|id| id.trim().parse().ok())
+            .collect(),
+    };
+}
+
+/// 是否为 admin_user_ids 中的管理员, 以最近一次加载的配置为准
+pub fn is_admin(user_id: u32) -> bool {
+    SETTINGS.read().unwrap().admin_user_ids.contains(&user_id)
+}
+
+/// 根据配置加载密钥环, 需在签发或校验 token 之前调用, 之后可随时重新加载以轮换密钥
+pub fn load_keys(cfg: &Config, active_kid: Option<&str>) -> Result<String, UserServerError> {
+    let key_ring = KeyRing::load(cfg, active_kid)?;
+    let kid = key_ring
+        .active()
+        .map(|key| key.kid.clone())
+        .unwrap_or_default();
+    info!(
+        "jwt key ring loaded, active kid {}, kids {:?}",
+        kid,
+        key_ring.kids()
     );
+    *KEY_RING.write().unwrap() = key_ring;
+    Ok(kid)
+}
+
+pub fn kids() -> Vec<String> {
+    KEY_RING.read().unwrap().kids()
+}
+
+/// 当前签名密钥的算法
+pub fn algorithm() -> Option<Algorithm> {
+    KEY_RING.read().unwrap().active().map(|key| key.algorithm)
+}
+
+/// 用当前密钥签名 access token 与 refresh token
+pub fn get_token<T: Serialize>(claims: &T) -> Result<String, UserServerError> {
+    let key = KEY_RING
+        .read()
+        .unwrap()
+        .active()
+        .ok_or_else(|| UserServerError::KeyError("jwt key is not loaded".to_string()))?;
+    let encoding_key = key
+        .encoding_key
+        .as_ref()
+        .ok_or_else(|| UserServerError::KeyError(format!("no signing key for kid {}", key.kid)))?;
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+    let token = encode(&header, claims, encoding_key);
     match token {
         Ok(token) => Ok(token),
         Err(err) => Err(UserServerError::JWTGenerationError(err.to_string())),
@@ -48,10 +148,93 @@ pub fn get_token(
 }
 
 pub fn verify(token: &str) -> Result<Claims, UserServerError> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(JWT_SECRET_KEY.as_bytes()),
-        &Validation::new(Algorithm::HS256),
-    )?;
+    let header = decode_header(token)?;
+    let key = KEY_RING
+        .read()
+        .unwrap()
+        .find(header.kid.as_deref())
+        .ok_or_else(|| UserServerError::JWTVerifyError("unknown signing key".to_string()))?;
+    let mut validation = Validation::new(key.algorithm);
+    {
+        let settings = SETTINGS.read().unwrap();
+        validation.leeway = settings.leeway;
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&settings.issuer]);
+        validation.set_audience(&settings.audiences);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud"]);
+    }
+    let token_data = decode::<Claims>(token, &key.decoding_key, &validation)?;
     Ok(token_data.claims)
 }
+
+/// 当前签名密钥是否为非对称算法, 只有这时依赖方才能用 JWK Set 校验 id_token
+pub fn signs_id_tokens() -> bool {
+    !matches!(
+        algorithm(),
+        None | Some(Algorithm::HS256) | Some(Algorithm::HS384) | Some(Algorithm::HS512)
+    )
+}
+
+/// 签发 id_token, 对称密钥是服务端的私有密钥, 依赖方无法校验, 此时拒绝签发
+pub fn get_id_token<T: Serialize>(claims: &T) -> Result<String, UserServerError> {
+    if !signs_id_tokens() {
+        return Err(UserServerError::KeyError(
+            "id_token requires an asymmetric signing key".to_string(),
+        ));
+    }
+    get_token(claims)
+}
+
+/// 可公开的验签公钥集合, 对称算法的密钥不会出现在其中
+pub fn jwks() -> JwkSet {
+    KEY_RING.read().unwrap().jwks()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims() -> Claims {
+        let config = Config::for_tests();
+        configure(&config);
+        load_keys(&config, None).unwrap();
+        Claims::new("normal".to_string(), 3600, 1, "".to_string())
+    }
+
+    fn rejected(claims: &Claims) -> bool {
+        matches!(
+            verify(&get_token(claims).unwrap()),
+            Err(UserServerError::JWTVerifyError(_))
+        )
+    }
+
+    #[test]
+    fn accepts_own_token() {
+        let claims = claims();
+        assert_eq!(
+            verify(&get_token(&claims).unwrap()).unwrap().jti,
+            claims.jti
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_audience() {
+        let mut claims = claims();
+        claims.aud = vec!["another-service".to_string()];
+        assert!(rejected(&claims));
+    }
+
+    #[test]
+    fn rejects_wrong_issuer() {
+        let mut claims = claims();
+        claims.iss = "another-issuer".to_string();
+        assert!(rejected(&claims));
+    }
+
+    #[test]
+    fn rejects_future_nbf() {
+        let mut claims = claims();
+        claims.nbf += 3600;
+        assert!(rejected(&claims));
+    }
+}
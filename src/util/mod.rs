@@ -1,3 +1,4 @@
+pub mod jwk;
 pub mod jwt;
 pub mod pagination;
 pub mod password;
@@ -1,23 +1,126 @@
+use crate::config::Config as ServerConfig;
 use crate::error::UserServerError;
 use argon2::{self, Config, ThreadMode, Variant, Version};
 use once_cell::sync::Lazy;
+use rand::{thread_rng, RngCore};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
 use tracing::error;
 
 static PASSWORD_SECRET_KEY: Lazy<String> =
     Lazy::new(|| std::env::var("PASSWORD_SECRET_KEY").expect("未设置 PASSWORD_SECRET_KEY"));
 
-const SALT: &'static [u8] = b"sorasupersecuresalt";
+static PARAMS: Lazy<RwLock<HashParams>> = Lazy::new(|| RwLock::new(HashParams::default()));
 
+/// 早期所有哈希共用的盐, 只用于识别需要重新哈希的旧数据
+const LEGACY_SALT: &[u8] = b"sorasupersecuresalt";
+
+/// 每次哈希随机生成的盐的长度 (字节)
+const SALT_LEN: usize = 16;
+
+/// 校准时内存开销的上限 (KiB)
+const CALIBRATION_MAX_MEMORY: u32 = 1024 * 1024;
+
+/// argon2 的参数, mem_cost 的单位为 KiB
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HashParams {
+    pub variant: Variant,
+    pub mem_cost: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+}
+
+/// 与之前固定使用的参数一致
+impl Default for HashParams {
+    fn default() -> HashParams {
+        HashParams {
+            variant: Variant::Argon2id,
+            mem_cost: 4096,
+            time_cost: 3,
+            lanes: 4,
+        }
+    }
+}
+
+impl HashParams {
+    pub fn from_config(cfg: &ServerConfig) -> Result<HashParams, UserServerError> {
+        let params = HashParams {
+            variant: Variant::from_str(&cfg.argon2_variant).map_err(|_| {
+                UserServerError::ConfigError(format!(
+                    "unknown argon2 variant {}",
+                    cfg.argon2_variant
+                ))
+            })?,
+            mem_cost: cfg.argon2_memory_cost,
+            time_cost: cfg.argon2_iterations,
+            lanes: cfg.argon2_parallelism,
+        };
+        if params.time_cost < 1
+            || params.lanes < 1
+            || params.lanes > 0x00FF_FFFF
+            || params.mem_cost < 8 * params.lanes
+        {
+            return Err(UserServerError::ConfigError(format!(
+                "invalid argon2 parameters m={} t={} p={}, memory cost must be at least 8 * parallelism",
+                params.mem_cost, params.time_cost, params.lanes
+            )));
+        }
+        Ok(params)
+    }
+
+    /// 解析编码后哈希中的参数, 格式为 $argon2id$v=19$m=4096,t=3,p=4$salt$hash
+    fn decode(hash: &str) -> Option<HashParams> {
+        let parts: Vec<&str> = hash.split('$').collect();
+        let (variant, params) = match parts[..] {
+            ["", variant, _, params, _, _] => (variant, params),
+            _ => return None,
+        };
+        let mut decoded = HashParams {
+            variant: Variant::from_str(variant).ok()?,
+            mem_cost: 0,
+            time_cost: 0,
+            lanes: 0,
+        };
+        for param in params.split(',') {
+            match param.split_once('=')? {
+                ("m", value) => decoded.mem_cost = value.parse().ok()?,
+                ("t", value) => decoded.time_cost = value.parse().ok()?,
+                ("p", value) => decoded.lanes = value.parse().ok()?,
+                _ => return None,
+            }
+        }
+        Some(decoded)
+    }
+
+    fn argon2_config(&self) -> Config<'static> {
+        Config {
+            variant: self.variant,
+            version: Version::Version13,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            thread_mode: ThreadMode::Parallel,
+            secret: PASSWORD_SECRET_KEY.as_bytes(),
+            ..Default::default()
+        }
+    }
+}
+
+/// 根据配置设置新哈希使用的参数, 需在哈希密码之前调用
+pub fn configure(cfg: &ServerConfig) -> Result<(), UserServerError> {
+    *PARAMS.write().unwrap() = HashParams::from_config(cfg)?;
+    Ok(())
+}
+
+/// 使用随机的盐, 盐编码在结果中, 校验时无需单独保存
 pub fn hash_password(password: &str) -> Result<String, UserServerError> {
-    let config = Config {
-        variant: Variant::Argon2id,
-        version: Version::Version13,
-        lanes: 4,
-        thread_mode: ThreadMode::Parallel,
-        secret: PASSWORD_SECRET_KEY.as_bytes(),
-        ..Default::default()
-    };
-    argon2::hash_encoded(password.as_bytes(), &SALT, &config).map_err(|err| {
+    hash_with(password, &PARAMS.read().unwrap())
+}
+
+fn hash_with(password: &str, params: &HashParams) -> Result<String, UserServerError> {
+    let mut salt = [0u8; SALT_LEN];
+    thread_rng().fill_bytes(&mut salt);
+    argon2::hash_encoded(password.as_bytes(), &salt, &params.argon2_config()).map_err(|err| {
         error!("{}", err.to_string());
         UserServerError::PasswordHashError(err.to_string())
     })
@@ -32,3 +135,148 @@ pub fn verify(hash: &str, password: &str) -> Result<bool, UserServerError> {
     )
     .map_err(|_| UserServerError::PasswordUnauthorizedError("密码认证失败".to_string()))
 }
+
+/// 使用旧的固定盐, 旧版本算法, 不同的变体或并行度, 或比配置更弱的内存与时间开销生成的哈希,
+/// 需要在校验成功后重新哈希
+pub fn needs_rehash(hash: &str) -> bool {
+    needs_rehash_with(hash, &PARAMS.read().unwrap())
+}
+
+fn needs_rehash_with(hash: &str, current: &HashParams) -> bool {
+    let legacy_salt = base64::encode_config(LEGACY_SALT, base64::STANDARD_NO_PAD);
+    if hash.split('$').nth(4) == Some(legacy_salt.as_str()) {
+        return true;
+    }
+    match HashParams::decode(hash) {
+        Some(params) => {
+            hash.split('$').nth(2) != Some("v=19")
+                || params.variant != current.variant
+                || params.lanes != current.lanes
+                || params.mem_cost < current.mem_cost
+                || params.time_cost < current.time_cost
+        }
+        None => false,
+    }
+}
+
+/// 在当前机器上寻找单次哈希耗时达到 target 的参数: 保持变体与并行度,
+/// 先倍增内存开销, 达到上限后再增加迭代次数. 返回参数与实测耗时
+pub fn calibrate(
+    base: HashParams,
+    target: Duration,
+) -> Result<(HashParams, Duration), UserServerError> {
+    let measure = |params: &HashParams| -> Result<Duration, UserServerError> {
+        let start = Instant::now();
+        hash_with("calibration password", params)?;
+        Ok(start.elapsed())
+    };
+    let mut params = HashParams {
+        mem_cost: (8 * base.lanes).max(8192),
+        time_cost: base.time_cost,
+        ..base
+    };
+    let mut elapsed = measure(&params)?;
+    while elapsed < target && params.mem_cost * 2 <= CALIBRATION_MAX_MEMORY {
+        let next = HashParams {
+            mem_cost: params.mem_cost * 2,
+            ..params
+        };
+        let next_elapsed = measure(&next)?;
+        if next_elapsed > target * 3 / 2 {
+            break;
+        }
+        params = next;
+        elapsed = next_elapsed;
+    }
+    while elapsed < target {
+        params.time_cost += 1;
+        elapsed = measure(&params)?;
+    }
+    Ok((params, elapsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALT: &str = "c29tZXJhbmRvbXNhbHQ";
+    const HASH: &str = "aGFzaA";
+
+    fn encoded(variant: &str, version: &str, m: u32, t: u32, p: u32, salt: &str) -> String {
+        format!(
+            "$argon2{}$v={}$m={},t={},p={}${}${}",
+            variant, version, m, t, p, salt, HASH
+        )
+    }
+
+    #[test]
+    fn current_parameters_kept() {
+        let current = HashParams::default();
+        assert!(!needs_rehash_with(
+            &encoded("id", "19", 4096, 3, 4, SALT),
+            &current
+        ));
+        // 比配置更强的参数无需降级
+        assert!(!needs_rehash_with(
+            &encoded("id", "19", 8192, 4, 4, SALT),
+            &current
+        ));
+    }
+
+    #[test]
+    fn legacy_salt_rehashed() {
+        let salt = base64::encode_config(LEGACY_SALT, base64::STANDARD_NO_PAD);
+        assert!(needs_rehash_with(
+            &encoded("id", "19", 4096, 3, 4, &salt),
+            &HashParams::default()
+        ));
+    }
+
+    #[test]
+    fn weaker_or_different_parameters_rehashed() {
+        let current = HashParams::default();
+        for hash in [
+            encoded("id", "19", 2048, 3, 4, SALT),
+            encoded("id", "19", 4096, 2, 4, SALT),
+            encoded("id", "19", 4096, 3, 2, SALT),
+            encoded("id", "19", 4096, 3, 8, SALT),
+            encoded("i", "19", 4096, 3, 4, SALT),
+            encoded("id", "16", 4096, 3, 4, SALT),
+        ] {
+            assert!(needs_rehash_with(&hash, &current), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn config_bounds() {
+        let mut config = ServerConfig::for_tests();
+        assert_eq!(
+            HashParams::from_config(&config).unwrap(),
+            HashParams {
+                variant: Variant::from_str(&config.argon2_variant).unwrap(),
+                mem_cost: config.argon2_memory_cost,
+                time_cost: config.argon2_iterations,
+                lanes: config.argon2_parallelism,
+            }
+        );
+
+        config.argon2_parallelism = 4;
+        config.argon2_memory_cost = 31;
+        assert!(HashParams::from_config(&config).is_err());
+        config.argon2_memory_cost = 32;
+        assert!(HashParams::from_config(&config).is_ok());
+
+        config.argon2_iterations = 0;
+        assert!(HashParams::from_config(&config).is_err());
+        config.argon2_iterations = 1;
+        config.argon2_parallelism = 0;
+        assert!(HashParams::from_config(&config).is_err());
+
+        config.argon2_parallelism = 4;
+        config.argon2_variant = "argon2x".to_string();
+        assert!(matches!(
+            HashParams::from_config(&config),
+            Err(UserServerError::ConfigError(_))
+        ));
+    }
+}
@@ -12,3 +12,12 @@ pub fn random_string(len: i8) -> String {
     info!("random string : {:?}", string);
     string
 }
+
+/// 不输出日志的随机字符串, 用于 token 句柄等敏感数据
+pub fn secret_string(len: usize) -> String {
+    iter::repeat(())
+        .map(|()| thread_rng().sample(Alphanumeric))
+        .map(char::from)
+        .take(len)
+        .collect()
+}
@@ -0,0 +1,113 @@
+use super::oauth::{basic_credentials, description, escape, html, json_response, token_error};
+use super::WebState;
+use crate::error::UserServerError;
+use crate::model::request::{DeviceAuthorizationParams, DeviceVerificationParams};
+use crate::service::device as device_service;
+use crate::service::oauth as oauth_service;
+use axum::extract::{Extension, Form};
+use http::header::HeaderMap;
+use http::{Response, StatusCode};
+use hyper::Body;
+use tracing::info;
+
+/// POST /device_authorization: 设备端申请 device_code 与 user_code
+pub async fn device_authorization(
+    Extension(state): Extension<WebState>,
+    Form(mut params): Form<DeviceAuthorizationParams>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Some((client_id, client_secret)) = basic_credentials(&headers) {
+        params.client_id = client_id;
+        params.client_secret = client_secret;
+    }
+    match device_service::device_authorization(
+        params,
+        state.db_pool.clone(),
+        state.redis_pool.clone(),
+        &state.config,
+    )
+    .await
+    {
+        Ok(authorization) => json_response(StatusCode::OK, &authorization),
+        Err(err) => {
+            info!("device authorization rejected: {}", err);
+            let code = oauth_service::error_code(&err);
+            let status = match code {
+                "invalid_client" => StatusCode::UNAUTHORIZED,
+                "server_error" => StatusCode::INTERNAL_SERVER_ERROR,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            token_error(status, code, &description(&err))
+        }
+    }
+}
+
+/// GET /device: 输入 user_code 并登录, verification_uri_complete 会预先填入 user_code
+pub async fn verification_form(
+    Extension(state): Extension<WebState>,
+    Form(params): Form<DeviceVerificationParams>,
+) -> Response<Body> {
+    if params.user_code.is_empty() {
+        return verification_page(&params, "", "");
+    }
+    let conn = &state.db_pool.get().unwrap();
+    match device_service::pending_device(conn, &state.redis_pool, &params.user_code) {
+        Ok((_, client, scope)) => verification_page(
+            &params,
+            &format!("{} 请求访问你的账号 ({})", client.name, scope),
+            "",
+        ),
+        Err(err) => verification_page(&params, "", &description(&err)),
+    }
+}
+
+/// POST /device: 登录后批准或拒绝设备的授权
+pub async fn verify(
+    Extension(state): Extension<WebState>,
+    Form(params): Form<DeviceVerificationParams>,
+) -> Response<Body> {
+    match device_service::verify_device(&state.db_pool, &state.redis_pool, &state.config, &params)
+        .await
+    {
+        Ok(true) => html(
+            StatusCode::OK,
+            "<p>已授权, 请回到设备上继续操作</p>".to_string(),
+        ),
+        Ok(false) => html(StatusCode::OK, "<p>已拒绝该设备的授权</p>".to_string()),
+        Err(UserServerError::PasswordUnauthorizedError(_)) => {
+            verification_page(&params, "", "邮箱或密码错误")
+        }
+        Err(err) => verification_page(&params, "", &description(&err)),
+    }
+}
+
+fn verification_page(
+    params: &DeviceVerificationParams,
+    request: &str,
+    message: &str,
+) -> Response<Body> {
+    html(
+        StatusCode::OK,
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>设备授权</title></head>
+<body>
+<p>{}</p>
+<p>{}</p>
+<form method="post" action="/device">
+<input type="text" name="user_code" value="{}" placeholder="XXXX-XXXX" required>
+<input type="email" name="email" value="{}" placeholder="email" required>
+<input type="password" name="password" placeholder="password" required>
+<button type="submit" name="action" value="approve">授权</button>
+<button type="submit" name="action" value="deny">拒绝</button>
+</form>
+</body>
+</html>"#,
+            escape(request),
+            escape(message),
+            escape(&params.user_code),
+            escape(&params.email)
+        ),
+    )
+}
@@ -0,0 +1,167 @@
+use super::oauth::{consent_page, description, error_page, redirect, redirect_error};
+use super::WebState;
+use crate::config::Config;
+use crate::error::UserServerError;
+use crate::model::request::{AuthorizeParams, FederatedCallbackParams};
+use crate::service::federation::{self as federation_service, BROWSER_COOKIE, FEDERATED_LOGIN_TTL};
+use crate::service::oauth::{self as oauth_service, Authorization};
+use crate::util::random;
+use axum::extract::{Extension, Form, Path};
+use http::header::{self, HeaderMap};
+use http::{Response, StatusCode};
+use hyper::Body;
+use tracing::info;
+
+/// 请求中绑定登录状态的 cookie, 不存在时为空
+fn browser_cookie(headers: &HeaderMap) -> String {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == BROWSER_COOKIE)
+        .map(|(_, value)| value.to_string())
+        .unwrap_or_default()
+}
+
+/// 只在回调路径上发送的 HttpOnly cookie, 有效期与登录状态一致.
+/// SameSite=Lax 时上游重定向回来的顶层 GET 请求仍会携带
+fn set_browser_cookie(config: &Config, browser: &str) -> String {
+    let secure = match config.public_url.starts_with("https://") {
+        true => "; Secure",
+        false => "",
+    };
+    format!(
+        "{}={}; Path=/federated; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        BROWSER_COOKIE, browser, FEDERATED_LOGIN_TTL, secure
+    )
+}
+
+/// GET /federated/:provider: 校验授权请求后跳转到上游身份提供方,
+/// 同一浏览器同时进行的多个登录共用一个 cookie
+pub async fn start(
+    Extension(state): Extension<WebState>,
+    Path(provider): Path<String>,
+    Form(params): Form<AuthorizeParams>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let redirect_uri = {
+        let conn = &state.db_pool.get().unwrap();
+        let (client, redirect_uri) = match oauth_service::check_client(conn, &params) {
+            Ok(result) => result,
+            Err(err) => return error_page(&err),
+        };
+        if let Err(err) = oauth_service::check_request(&client, &params) {
+            return redirect_error(
+                &redirect_uri,
+                oauth_service::error_code(&err),
+                &description(&err),
+                &params.state,
+            );
+        }
+        redirect_uri
+    };
+    let oauth_state = params.state.clone();
+    let browser = match browser_cookie(&headers) {
+        browser if browser.len() == 43 => browser,
+        _ => random::secret_string(43),
+    };
+    match federation_service::start(
+        &state.redis_pool,
+        &state.config,
+        &provider,
+        params,
+        &browser,
+    )
+    .await
+    {
+        Ok(url) => Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, url)
+            .header(header::CACHE_CONTROL, "no-store")
+            .header(
+                header::SET_COOKIE,
+                set_browser_cookie(&state.config, &browser),
+            )
+            .body(Body::empty())
+            .unwrap(),
+        Err(err @ UserServerError::NotFound(_)) => error_page(&err),
+        Err(err) => redirect_error(
+            &redirect_uri,
+            oauth_service::error_code(&err),
+            &description(&err),
+            &oauth_state,
+        ),
+    }
+}
+
+/// GET /federated/callback: 上游登录成功后关联或创建本地用户, 再按授权请求签发授权码.
+/// state 须由同一浏览器发起, 否则拒绝
+pub async fn callback(
+    Extension(state): Extension<WebState>,
+    Form(params): Form<FederatedCallbackParams>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let browser = browser_cookie(&headers);
+    let login = match federation_service::take_login(&state.redis_pool, &params.state, &browser) {
+        Ok(login) => login,
+        Err(err) => return error_page(&err),
+    };
+    let redirect_uri = {
+        let conn = &state.db_pool.get().unwrap();
+        match oauth_service::check_client(conn, &login.params) {
+            Ok((_, redirect_uri)) => redirect_uri,
+            Err(err) => return error_page(&err),
+        }
+    };
+    let oauth_state = login.params.state.clone();
+    if !params.error.is_empty() {
+        info!("{} login failed: {}", login.provider, params.error);
+        return redirect_error(
+            &redirect_uri,
+            "access_denied",
+            "the identity provider denied the login",
+            &oauth_state,
+        );
+    }
+
+    let authenticated = federation_service::authenticate(
+        state.db_pool.clone(),
+        &state.config,
+        &login,
+        &params.code,
+    )
+    .await;
+    let conn = &state.db_pool.get().unwrap();
+    let result = authenticated.and_then(|(user_id, email)| {
+        oauth_service::authorize_user(
+            conn,
+            &state.redis_pool,
+            &state.config,
+            &login.params,
+            user_id,
+            email,
+        )
+    });
+    match result {
+        Ok((Authorization::Code(code), redirect_uri)) => {
+            redirect(&redirect_uri, &[("code", &code), ("state", &oauth_state)])
+        }
+        Ok((Authorization::ConsentRequired(ticket, scope), _)) => {
+            match oauth_service::check_client(conn, &login.params) {
+                Ok((client, _)) => consent_page(&client.name, &ticket, &scope),
+                Err(err) => error_page(&err),
+            }
+        }
+        Err(err) => {
+            info!("{} login rejected: {}", login.provider, err);
+            redirect_error(
+                &redirect_uri,
+                oauth_service::error_code(&err),
+                &description(&err),
+                &oauth_state,
+            )
+        }
+    }
+}
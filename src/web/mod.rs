@@ -0,0 +1,54 @@
+use crate::config::{Config, DbPool, RedisPool};
+use axum::handler::{get, post};
+use axum::{AddExtensionLayer, Router};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+mod device;
+mod federated;
+mod oauth;
+mod registration;
+mod well_known;
+
+/// HTTP 接口共享的连接池与配置
+#[derive(Clone)]
+pub struct WebState {
+    pub db_pool: DbPool,
+    pub redis_pool: RedisPool,
+    pub config: Arc<Config>,
+}
+
+/// 供资源服务器与浏览器, 移动端等 HTTP 客户端访问的接口
+pub async fn serve(addr: SocketAddr, state: WebState) -> Result<(), hyper::Error> {
+    let app = Router::new()
+        .route("/.well-known/jwks.json", get(well_known::jwks))
+        .route(
+            "/authorize",
+            get(oauth::authorize_form).post(oauth::authorize),
+        )
+        .route("/token", post(oauth::token))
+        .route(
+            "/.well-known/openid-configuration",
+            get(well_known::openid_configuration),
+        )
+        .route("/device_authorization", post(device::device_authorization))
+        .route(
+            "/device",
+            get(device::verification_form).post(device::verify),
+        )
+        .route("/federated/callback", get(federated::callback))
+        .route("/federated/:provider", get(federated::start))
+        .route("/userinfo", get(oauth::userinfo).post(oauth::userinfo))
+        .route("/register", post(registration::register))
+        .route(
+            "/register/:client_id",
+            get(registration::read)
+                .put(registration::update)
+                .delete(registration::delete),
+        )
+        .layer(AddExtensionLayer::new(state));
+
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await
+}
@@ -0,0 +1,458 @@
+use super::WebState;
+use crate::config::Config;
+use crate::error::UserServerError;
+use crate::middleware::auth;
+use crate::model::request::{AuthorizeParams, TokenParams};
+use crate::service::client::DEVICE_CODE_GRANT;
+use crate::service::device as device_service;
+use crate::service::federation as federation_service;
+use crate::service::oauth::{self as oauth_service, Authorization};
+use axum::extract::{Extension, Form};
+use http::header::{self, HeaderMap};
+use http::{Response, StatusCode};
+use hyper::Body;
+use serde::Serialize;
+use serde_json::json;
+use tracing::{error, info};
+use url::{form_urlencoded, Url};
+
+/// GET /authorize: 校验授权请求后显示登录表单
+pub async fn authorize_form(
+    Extension(state): Extension<WebState>,
+    Form(params): Form<AuthorizeParams>,
+) -> Response<Body> {
+    let conn = &state.db_pool.get().unwrap();
+    let (client, redirect_uri) = match oauth_service::check_client(conn, &params) {
+        Ok(result) => result,
+        Err(err) => return error_page(&err),
+    };
+    if params.response_type != "code" {
+        return redirect_error(
+            &redirect_uri,
+            "unsupported_response_type",
+            "response_type must be code",
+            &params.state,
+        );
+    }
+    if let Err(err) = oauth_service::check_request(&client, &params) {
+        return redirect_error(
+            &redirect_uri,
+            oauth_service::error_code(&err),
+            &description(&err),
+            &params.state,
+        );
+    }
+    login_page(&state.config, &client.name, &params, "")
+}
+
+/// POST /authorize: 登录成功后携带授权码重定向回客户端, 密码错误时重新显示登录表单,
+/// 尚未同意申请的 scope 时显示同意页面
+pub async fn authorize(
+    Extension(state): Extension<WebState>,
+    Form(params): Form<AuthorizeParams>,
+) -> Response<Body> {
+    if !params.consent_ticket.is_empty() {
+        return consent(&state, &params);
+    }
+    let (client, redirect_uri) = {
+        let conn = &state.db_pool.get().unwrap();
+        match oauth_service::check_client(conn, &params) {
+            Ok(result) => result,
+            Err(err) => return error_page(&err),
+        }
+    };
+    if params.response_type != "code" {
+        return redirect_error(
+            &redirect_uri,
+            "unsupported_response_type",
+            "response_type must be code",
+            &params.state,
+        );
+    }
+    match oauth_service::authorize(&state.db_pool, &state.redis_pool, &state.config, &params).await
+    {
+        Ok((Authorization::Code(code), redirect_uri)) => {
+            redirect(&redirect_uri, &[("code", &code), ("state", &params.state)])
+        }
+        Ok((Authorization::ConsentRequired(ticket, scope), _)) => {
+            consent_page(&client.name, &ticket, &scope)
+        }
+        Err(UserServerError::PasswordUnauthorizedError(_)) => {
+            login_page(&state.config, &client.name, &params, "邮箱或密码错误")
+        }
+        Err(err) => redirect_error(
+            &redirect_uri,
+            oauth_service::error_code(&err),
+            &description(&err),
+            &params.state,
+        ),
+    }
+}
+
+/// 同意页面提交: 同意时记录授权并签发授权码, 拒绝时以 access_denied 重定向
+fn consent(state: &WebState, params: &AuthorizeParams) -> Response<Body> {
+    let pending = match oauth_service::take_consent(&state.redis_pool, &params.consent_ticket) {
+        Ok(pending) => pending,
+        Err(err) => return error_page(&err),
+    };
+    let conn = &state.db_pool.get().unwrap();
+    let (_, redirect_uri) = match oauth_service::check_client(conn, &pending.params) {
+        Ok(result) => result,
+        Err(err) => return error_page(&err),
+    };
+    let oauth_state = pending.params.state.clone();
+    if params.consent != "approve" {
+        info!(
+            "user {} denied client {}",
+            pending.user_id, pending.params.client_id
+        );
+        return redirect_error(
+            &redirect_uri,
+            "access_denied",
+            "the user denied the request",
+            &oauth_state,
+        );
+    }
+    match oauth_service::approve(conn, &state.redis_pool, &state.config, pending) {
+        Ok(code) => redirect(&redirect_uri, &[("code", &code), ("state", &oauth_state)]),
+        Err(err) => redirect_error(
+            &redirect_uri,
+            oauth_service::error_code(&err),
+            &description(&err),
+            &oauth_state,
+        ),
+    }
+}
+
+/// POST /token: 支持 client_secret_basic 与 client_secret_post 两种客户端认证方式,
+/// 错误按 RFC 6749 5.2 返回, invalid_client 时为 401
+pub async fn token(
+    Extension(state): Extension<WebState>,
+    Form(mut params): Form<TokenParams>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Some((client_id, client_secret)) = basic_credentials(&headers) {
+        if !params.client_id.is_empty() && params.client_id != client_id {
+            return token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+                "client_id mismatch",
+            );
+        }
+        params.client_id = client_id;
+        params.client_secret = client_secret;
+    }
+    let result = match params.grant_type.as_str() {
+        "authorization_code" => {
+            oauth_service::exchange_code(
+                params,
+                state.db_pool.clone(),
+                state.redis_pool.clone(),
+                &state.config,
+            )
+            .await
+        }
+        "password" => {
+            oauth_service::password_grant(
+                params,
+                state.db_pool.clone(),
+                state.redis_pool.clone(),
+                state.config.clone(),
+            )
+            .await
+        }
+        "refresh_token" => {
+            oauth_service::refresh_grant(
+                params,
+                state.db_pool.clone(),
+                state.redis_pool.clone(),
+                state.config.clone(),
+            )
+            .await
+        }
+        "client_credentials" => {
+            oauth_service::client_credentials_grant(
+                params,
+                state.db_pool.clone(),
+                state.redis_pool.clone(),
+                &state.config,
+            )
+            .await
+        }
+        DEVICE_CODE_GRANT => {
+            device_service::poll_device_code(
+                params,
+                state.db_pool.clone(),
+                state.redis_pool.clone(),
+                &state.config,
+            )
+            .await
+        }
+        _ => {
+            return token_error(
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+                "grant_type not supported",
+            )
+        }
+    };
+    match result {
+        Ok(token) => json_response(StatusCode::OK, &token),
+        Err(err) => {
+            info!("token request rejected: {}", err);
+            let code = oauth_service::error_code(&err);
+            let status = match code {
+                "invalid_client" => StatusCode::UNAUTHORIZED,
+                "server_error" => StatusCode::INTERNAL_SERVER_ERROR,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            let mut response = token_error(status, code, &description(&err));
+            // RFC 6749 5.2: 通过 Authorization 头认证失败时须返回 WWW-Authenticate
+            if status == StatusCode::UNAUTHORIZED && headers.contains_key(header::AUTHORIZATION) {
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    header::HeaderValue::from_static("Basic realm=\"token\""),
+                );
+            }
+            response
+        }
+    }
+}
+
+/// GET/POST /userinfo: 使用带有 openid scope 的 access token 获取用户信息
+pub async fn userinfo(Extension(state): Extension<WebState>, headers: HeaderMap) -> Response<Body> {
+    let token_info = match auth::authenticate(&state.redis_pool, &headers) {
+        Ok(token_info) => token_info,
+        Err(err) => {
+            info!("userinfo rejected: {}", err);
+            return bearer_error(StatusCode::UNAUTHORIZED, "invalid_token");
+        }
+    };
+    match oauth_service::user_info(token_info, state.db_pool.clone()).await {
+        Ok(user_info) => json_response(StatusCode::OK, &user_info),
+        Err(UserServerError::PermissionDenied(_)) => {
+            bearer_error(StatusCode::FORBIDDEN, "insufficient_scope")
+        }
+        Err(err) => {
+            error!("userinfo error: {}", err);
+            token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "internal server error",
+            )
+        }
+    }
+}
+
+/// RFC 6750 3: 在 WWW-Authenticate 中返回错误
+pub(super) fn bearer_error(status: StatusCode, code: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(
+            header::WWW_AUTHENTICATE,
+            format!("Bearer error=\"{}\"", code),
+        )
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// 不向客户端暴露内部错误的细节
+pub(super) fn description(err: &UserServerError) -> String {
+    match oauth_service::error_code(err) {
+        "server_error" => {
+            error!("{}", err);
+            "internal server error".to_string()
+        }
+        _ => err.to_string(),
+    }
+}
+
+/// 解析 Authorization: Basic base64(client_id:client_secret)
+pub(super) fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    Some((client_id.to_string(), client_secret.to_string()))
+}
+
+pub(super) fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(header::PRAGMA, "no-cache")
+        .body(Body::from(serde_json::to_vec(body).unwrap_or_default()))
+        .unwrap()
+}
+
+pub(super) fn token_error(status: StatusCode, code: &str, description: &str) -> Response<Body> {
+    json_response(
+        status,
+        &json!({ "error": code, "error_description": description }),
+    )
+}
+
+pub(super) fn redirect(redirect_uri: &str, params: &[(&str, &str)]) -> Response<Body> {
+    let mut url = match Url::parse(redirect_uri) {
+        Ok(url) => url,
+        Err(_) => {
+            return html(
+                StatusCode::BAD_REQUEST,
+                "<p>invalid redirect_uri</p>".to_string(),
+            )
+        }
+    };
+    {
+        let mut query = url.query_pairs_mut();
+        for (key, value) in params.iter().filter(|(_, value)| !value.is_empty()) {
+            query.append_pair(key, value);
+        }
+    }
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header(header::LOCATION, url.as_str())
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::empty())
+        .unwrap()
+}
+
+pub(super) fn redirect_error(
+    redirect_uri: &str,
+    code: &str,
+    description: &str,
+    state: &str,
+) -> Response<Body> {
+    redirect(
+        redirect_uri,
+        &[
+            ("error", code),
+            ("error_description", description),
+            ("state", state),
+        ],
+    )
+}
+
+pub(super) fn html(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// client_id 或 redirect_uri 无效时不能重定向, 直接显示错误
+pub(super) fn error_page(err: &UserServerError) -> Response<Body> {
+    info!("authorization request rejected: {}", err);
+    html(
+        StatusCode::BAD_REQUEST,
+        format!("<p>{}</p>", escape(&description(err))),
+    )
+}
+
+/// 配置了上游身份提供方时同时显示对应的登录链接, 链接携带授权请求的参数
+fn login_page(
+    config: &Config,
+    client_name: &str,
+    params: &AuthorizeParams,
+    message: &str,
+) -> Response<Body> {
+    let fields = [
+        ("response_type", &params.response_type),
+        ("client_id", &params.client_id),
+        ("redirect_uri", &params.redirect_uri),
+        ("scope", &params.scope),
+        ("state", &params.state),
+        ("code_challenge", &params.code_challenge),
+        ("code_challenge_method", &params.code_challenge_method),
+        ("nonce", &params.nonce),
+    ];
+    let hidden: String = fields
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                r#"<input type="hidden" name="{}" value="{}">"#,
+                name,
+                escape(value)
+            )
+        })
+        .collect();
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(fields.iter().filter(|(_, value)| !value.is_empty()))
+        .finish();
+    let providers: String = federation_service::providers(config)
+        .into_iter()
+        .map(|(name, display_name)| {
+            format!(
+                r#"<p><a href="/federated/{}?{}">使用 {} 登录</a></p>"#,
+                escape(name),
+                escape(&query),
+                escape(display_name)
+            )
+        })
+        .collect();
+    html(
+        StatusCode::OK,
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>登录</title></head>
+<body>
+<p>{} 请求访问你的账号 ({})</p>
+<p>{}</p>
+<form method="post" action="/authorize">
+{}
+<input type="email" name="email" value="{}" placeholder="email" required>
+<input type="password" name="password" placeholder="password" required>
+<button type="submit">登录</button>
+</form>
+{}
+</body>
+</html>"#,
+            escape(client_name),
+            escape(&params.scope),
+            escape(message),
+            hidden,
+            escape(&params.email),
+            providers
+        ),
+    )
+}
+
+pub(super) fn consent_page(client_name: &str, ticket: &str, scope: &str) -> Response<Body> {
+    let scopes: String = scope
+        .split_whitespace()
+        .map(|item| format!("<li>{}</li>", escape(item)))
+        .collect();
+    html(
+        StatusCode::OK,
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>授权</title></head>
+<body>
+<p>{} 请求以下权限:</p>
+<ul>{}</ul>
+<form method="post" action="/authorize">
+<input type="hidden" name="consent_ticket" value="{}">
+<button type="submit" name="consent" value="approve">同意</button>
+<button type="submit" name="consent" value="deny">拒绝</button>
+</form>
+</body>
+</html>"#,
+            escape(client_name),
+            scopes,
+            escape(ticket)
+        ),
+    )
+}
+
+pub(super) fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,133 @@
+use super::oauth::{bearer_error, description, json_response, token_error};
+use super::WebState;
+use crate::error::UserServerError;
+use crate::model::request::ClientMetadata;
+use crate::service::oauth as oauth_service;
+use crate::service::registration as registration_service;
+use axum::extract::{Extension, Path};
+use http::header::{self, HeaderMap};
+use http::{Response, StatusCode};
+use hyper::body::Bytes;
+use hyper::Body;
+use tracing::info;
+
+/// Authorization: Bearer 中的 initial access token 或 registration access token
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+    if token.is_empty() {
+        None
+    } else {
+        Some(token.to_string())
+    }
+}
+
+/// 请求体不是合法的 JSON 时同样返回 invalid_client_metadata
+fn parse_metadata(body: &Bytes) -> Result<ClientMetadata, UserServerError> {
+    serde_json::from_slice(body)
+        .map_err(|err| UserServerError::InvalidClientMetadata(err.to_string()))
+}
+
+/// RFC 7591 3.2.2: 元数据错误返回 400, token 无效返回 401
+fn registration_error(err: UserServerError) -> Response<Body> {
+    info!("client registration request rejected: {}", err);
+    match oauth_service::error_code(&err) {
+        "invalid_token" => bearer_error(StatusCode::UNAUTHORIZED, "invalid_token"),
+        "server_error" => token_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "server_error",
+            &description(&err),
+        ),
+        code => token_error(StatusCode::BAD_REQUEST, code, &description(&err)),
+    }
+}
+
+/// POST /register: 动态注册客户端
+pub async fn register(
+    Extension(state): Extension<WebState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let metadata = match parse_metadata(&body) {
+        Ok(metadata) => metadata,
+        Err(err) => return registration_error(err),
+    };
+    match registration_service::register(
+        metadata,
+        bearer_token(&headers),
+        state.db_pool.clone(),
+        state.redis_pool.clone(),
+        &state.config,
+    )
+    .await
+    {
+        Ok(registration) => json_response(StatusCode::CREATED, &registration),
+        Err(err) => registration_error(err),
+    }
+}
+
+/// GET /register/:client_id: 读取客户端配置
+pub async fn read(
+    Extension(state): Extension<WebState>,
+    Path(client_id): Path<String>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    match registration_service::read(
+        client_id,
+        bearer_token(&headers).unwrap_or_default(),
+        state.db_pool.clone(),
+        &state.config,
+    )
+    .await
+    {
+        Ok(registration) => json_response(StatusCode::OK, &registration),
+        Err(err) => registration_error(err),
+    }
+}
+
+/// PUT /register/:client_id: 替换客户端配置
+pub async fn update(
+    Extension(state): Extension<WebState>,
+    Path(client_id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let metadata = match parse_metadata(&body) {
+        Ok(metadata) => metadata,
+        Err(err) => return registration_error(err),
+    };
+    match registration_service::update(
+        client_id,
+        bearer_token(&headers).unwrap_or_default(),
+        metadata,
+        state.db_pool.clone(),
+        &state.config,
+    )
+    .await
+    {
+        Ok(registration) => json_response(StatusCode::OK, &registration),
+        Err(err) => registration_error(err),
+    }
+}
+
+/// DELETE /register/:client_id: 注销客户端
+pub async fn delete(
+    Extension(state): Extension<WebState>,
+    Path(client_id): Path<String>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    match registration_service::delete(
+        client_id,
+        bearer_token(&headers).unwrap_or_default(),
+        state.db_pool.clone(),
+    )
+    .await
+    {
+        Ok(()) => Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(Body::empty())
+            .unwrap(),
+        Err(err) => registration_error(err),
+    }
+}
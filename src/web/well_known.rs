@@ -0,0 +1,46 @@
+use super::WebState;
+use crate::service::client::DEVICE_CODE_GRANT;
+use crate::util::jwt;
+use axum::extract::Extension;
+use axum::Json;
+use jsonwebtoken::jwk::JwkSet;
+use serde_json::{json, Value};
+
+pub async fn jwks() -> Json<JwkSet> {
+    Json(jwt::jwks())
+}
+
+/// OpenID Connect discovery, issuer 为 jwt_issuer, 使用 OIDC 时应与 public_url 一致
+pub async fn openid_configuration(Extension(state): Extension<WebState>) -> Json<Value> {
+    let config = &state.config;
+    let url = config.public_url.trim_end_matches('/');
+    Json(json!({
+        "issuer": config.jwt_issuer,
+        "authorization_endpoint": format!("{}/authorize", url),
+        "token_endpoint": format!("{}/token", url),
+        "userinfo_endpoint": format!("{}/userinfo", url),
+        "device_authorization_endpoint": format!("{}/device_authorization", url),
+        "jwks_uri": format!("{}/.well-known/jwks.json", url),
+        "registration_endpoint": format!("{}/register", url),
+        "response_types_supported": ["code"],
+        "grant_types_supported": [
+            "authorization_code",
+            "password",
+            "refresh_token",
+            "client_credentials",
+            DEVICE_CODE_GRANT
+        ],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": jwt::algorithm()
+            .filter(|_| jwt::signs_id_tokens())
+            .into_iter()
+            .collect::<Vec<_>>(),
+        "scopes_supported": config.scope.split_whitespace().collect::<Vec<_>>(),
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"],
+        "code_challenge_methods_supported": ["S256"],
+        "claims_supported": [
+            "sub", "iss", "aud", "exp", "iat", "auth_time", "nonce",
+            "email", "nickname", "gender", "birthdate"
+        ],
+    }))
+}